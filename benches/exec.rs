@@ -0,0 +1,237 @@
+//! Opcode throughput benchmarks.
+//!
+//! Run with `cargo bench`. None of the scripts need real keys: the multisig
+//! benchmark uses 33-byte public keys, which Tapscript treats as an unknown
+//! key type and accepts without doing any signature verification.
+
+use bitcoin::hashes::Hash;
+use bitcoin::opcodes::all::*;
+use bitcoin::script::{Builder, ScriptBuf};
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::taproot::TapLeafHash;
+use bitcoin::{absolute, transaction, Transaction};
+use bitcoin_scriptexec::utils::{scriptint_arr, scriptint_vec};
+use bitcoin_scriptexec::{
+    execute_script_with_witness, Exec, ExecCtx, ExecLimits, Options, Stack, TxTemplate,
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+fn run(script: ScriptBuf, witness: Vec<Vec<u8>>, opt: Options) -> bool {
+    let tx = TxTemplate {
+        tx: Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![],
+        },
+        prevouts: vec![],
+        input_idx: 0,
+        taproot_annex_scriptleaf: Some((TapLeafHash::all_zeros(), None)),
+    };
+    let mut exec = Exec::new(ExecCtx::Tapscript, opt, tx, script, witness).unwrap();
+    while exec.exec_next().is_ok() {}
+    exec.result().unwrap().success
+}
+
+fn dup_drop_script(n: usize) -> ScriptBuf {
+    let mut b = Builder::new().push_int(1);
+    for _ in 0..n {
+        b = b.push_opcode(OP_DUP).push_opcode(OP_DROP);
+    }
+    b.into_script()
+}
+
+fn arithmetic_script(n: usize) -> ScriptBuf {
+    let mut b = Builder::new().push_int(0);
+    for _ in 0..n {
+        b = b.push_int(3).push_opcode(OP_ADD).push_opcode(OP_1SUB);
+    }
+    b.into_script()
+}
+
+fn hash_script(n: usize) -> ScriptBuf {
+    let mut b = Builder::new().push_slice([0x42; 32]);
+    for _ in 0..n {
+        b = b.push_opcode(OP_SHA256);
+    }
+    b.into_script()
+}
+
+fn multisig(n: usize) -> (ScriptBuf, Vec<Vec<u8>>) {
+    let mut b = Builder::new();
+    for i in 0..n {
+        b = b.push_slice([0x02; 33]);
+        b = if i == 0 {
+            b.push_opcode(OP_CHECKSIG)
+        } else {
+            b.push_opcode(OP_CHECKSIGADD)
+        };
+    }
+    let script = b.push_int(n as i64).push_opcode(OP_NUMEQUAL).into_script();
+    let witness = vec![vec![0x01; 64]; n];
+    (script, witness)
+}
+
+fn bench_scripts(c: &mut Criterion) {
+    let mut g = c.benchmark_group("exec");
+
+    let n = 400;
+    let script = dup_drop_script(n);
+    g.throughput(Throughput::Elements(2 * n as u64));
+    g.bench_function("dup_drop", |b| {
+        b.iter(|| execute_script_with_witness(black_box(script.clone()), vec![]))
+    });
+
+    let script = arithmetic_script(n);
+    g.throughput(Throughput::Elements(3 * n as u64));
+    g.bench_function("arithmetic", |b| {
+        b.iter(|| execute_script_with_witness(black_box(script.clone()), vec![]))
+    });
+
+    let script = hash_script(n);
+    g.throughput(Throughput::Elements(n as u64));
+    g.bench_function("sha256", |b| {
+        b.iter(|| execute_script_with_witness(black_box(script.clone()), vec![]))
+    });
+
+    let n = 100;
+    let (script, witness) = multisig(n);
+    g.throughput(Throughput::Elements(2 * n as u64));
+    g.bench_function("checksigadd_multisig", |b| {
+        b.iter(|| execute_script_with_witness(black_box(script.clone()), witness.clone()))
+    });
+
+    g.finish();
+}
+
+/// Rolls the bottom element of a stack of [depth] elements to the top [n] times.
+fn deep_roll_script(depth: usize, n: usize) -> ScriptBuf {
+    let mut b = Builder::new();
+    for _ in 0..n {
+        b = b.push_int(depth as i64 - 1).push_opcode(OP_ROLL);
+    }
+    b.into_script()
+}
+
+fn bench_roll(c: &mut Criterion) {
+    let mut g = c.benchmark_group("deep_roll");
+
+    // Rolling a fixed number of times shows the cost growing with stack depth.
+    let n = 200;
+    for depth in [100, 400, 900] {
+        let script = deep_roll_script(depth, n);
+        let witness = vec![vec![0x42]; depth];
+        g.throughput(Throughput::Elements(n as u64));
+        g.bench_with_input(BenchmarkId::new("unlimited", depth), &depth, |b, _| {
+            b.iter(|| {
+                run(
+                    black_box(script.clone()),
+                    witness.clone(),
+                    Options::default(),
+                )
+            })
+        });
+
+        // With a displacement cap, execution aborts after a bounded amount of work.
+        let opt = Options {
+            limits: ExecLimits {
+                max_roll_displacement: Some(10_000),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        g.bench_with_input(BenchmarkId::new("limited", depth), &depth, |b, _| {
+            b.iter(|| run(black_box(script.clone()), witness.clone(), opt.clone()))
+        });
+    }
+
+    g.finish();
+}
+
+fn bench_stack(c: &mut Criterion) {
+    let mut g = c.benchmark_group("stack");
+
+    let n = 1000;
+    g.throughput(Throughput::Elements(n as u64));
+    g.bench_function("push_pop_num", |b| {
+        b.iter(|| {
+            let mut stack = Stack::new();
+            for i in 0..n {
+                stack.pushnum(black_box(i));
+            }
+            for _ in 0..n {
+                black_box(stack.popnum(true).unwrap());
+            }
+        })
+    });
+    g.bench_function("push_pop_str", |b| {
+        b.iter(|| {
+            let mut stack = Stack::new();
+            for _ in 0..n {
+                stack.pushstr(black_box(&[0x42; 32]));
+            }
+            for _ in 0..n {
+                black_box(stack.popstr().unwrap());
+            }
+        })
+    });
+
+    // Encoding numbers into a fixed buffer avoids allocating for each of them.
+    g.bench_function("scriptint_vec", |b| {
+        b.iter(|| {
+            for i in 0..n {
+                black_box(scriptint_vec(black_box(i * 1000)).len());
+            }
+        })
+    });
+    g.bench_function("scriptint_arr", |b| {
+        b.iter(|| {
+            for i in 0..n {
+                black_box(scriptint_arr(black_box(i * 1000)).1);
+            }
+        })
+    });
+
+    g.finish();
+}
+
+fn bench_secp_context(c: &mut Criterion) {
+    let mut g = c.benchmark_group("secp_context");
+
+    let secp = Secp256k1::new();
+    let keypair = secp256k1::Keypair::from_seckey_slice(&secp, &[1; 32]).unwrap();
+    let (pubkey, _) = keypair.x_only_public_key();
+    let msg = secp256k1::Message::from_digest([0x42; 32]);
+    let sig = secp.sign_schnorr_no_aux_rand(&msg, &keypair);
+
+    // Signature checks reuse one context, this compares that with creating one per check.
+    let n = 100;
+    g.throughput(Throughput::Elements(n as u64));
+    g.bench_function("shared", |b| {
+        let secp = Secp256k1::verification_only();
+        b.iter(|| {
+            for _ in 0..n {
+                black_box(secp.verify_schnorr(&sig, &msg, &pubkey).is_ok());
+            }
+        })
+    });
+    g.bench_function("per_call", |b| {
+        b.iter(|| {
+            for _ in 0..n {
+                let secp = Secp256k1::verification_only();
+                black_box(secp.verify_schnorr(&sig, &msg, &pubkey).is_ok());
+            }
+        })
+    });
+
+    g.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_scripts,
+    bench_roll,
+    bench_stack,
+    bench_secp_context
+);
+criterion_main!(benches);
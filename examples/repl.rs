@@ -0,0 +1,144 @@
+//! An interactive script interpreter.
+//!
+//! Run with `cargo run --example repl`. Every line is a space-separated list
+//! of opcodes (`OP_DUP` or `DUP`), numbers (`42`) and hex data pushes
+//! (`0x0102`), which is appended to the script and executed in Tapscript. The
+//! stack is printed after every line. Lines starting with `:` are commands,
+//! see `:help`.
+
+use std::io::{self, BufRead, Write};
+
+use bitcoin::hashes::Hash;
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::opcodes::Opcode;
+use bitcoin::script::{Builder, PushBytesBuf, ScriptBuf};
+use bitcoin::taproot::TapLeafHash;
+use bitcoin::{absolute, transaction, Transaction};
+use bitcoin_scriptexec::{Exec, ExecCtx, Options, Stack, TxTemplate};
+
+const HELP: &str = "\
+commands:
+  :stack     print the stack
+  :altstack  print the altstack
+  :script    print the script executed so far
+  :reset     start over with an empty script
+  :help      print this message
+  :quit      exit";
+
+/// Parses a line of opcodes, numbers and hex pushes.
+fn parse_line(line: &str) -> Result<ScriptBuf, String> {
+    let mut b = Builder::new();
+    for token in line.split_whitespace() {
+        if let Some(hex) = token.strip_prefix("0x") {
+            let data = Vec::<u8>::from_hex(hex).map_err(|e| format!("{}: {}", token, e))?;
+            let data = PushBytesBuf::try_from(data).map_err(|e| format!("{}: {}", token, e))?;
+            b = b.push_slice(data);
+        } else if let Ok(n) = token.parse::<i64>() {
+            b = b.push_int(n);
+        } else {
+            let name = token.to_uppercase();
+            let name = if name.starts_with("OP_") {
+                name
+            } else {
+                format!("OP_{}", name)
+            };
+            let op = (0..=u8::MAX)
+                .map(Opcode::from)
+                .find(|op| op.to_string() == name)
+                .ok_or_else(|| format!("unknown opcode: {}", token))?;
+            b = b.push_opcode(op);
+        }
+    }
+    Ok(b.into_script())
+}
+
+fn new_exec(script: ScriptBuf) -> Exec {
+    let tx = TxTemplate {
+        tx: Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![],
+        },
+        prevouts: vec![],
+        input_idx: 0,
+        taproot_annex_scriptleaf: Some((TapLeafHash::all_zeros(), None)),
+    };
+    Exec::new(ExecCtx::Tapscript, Options::default(), tx, script, vec![]).expect("valid script")
+}
+
+/// Executes all of [script], without running the final stack checks.
+fn run(script: ScriptBuf) -> Result<Exec, String> {
+    let mut exec = new_exec(script);
+    while !exec.remaining_script().is_empty() {
+        if let Err(res) = exec.exec_next() {
+            let err = res.error.clone().expect("failed before the end");
+            return Err(match res.opcode {
+                Some(op) => format!("{:?} at {}", err, op),
+                None => format!("{:?}", err),
+            });
+        }
+    }
+    Ok(exec)
+}
+
+fn print_stack(stack: &Stack) {
+    if stack.is_empty() {
+        println!("  <empty>");
+    }
+    for (i, item) in stack.iter_str().enumerate().rev() {
+        println!("  {:>3}: {}", i, item.as_hex());
+    }
+}
+
+fn main() {
+    let mut script = ScriptBuf::new();
+    let mut exec = new_exec(script.clone());
+
+    println!("Bitcoin script REPL, type :help for commands.");
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        let line = line.trim();
+
+        match line {
+            "" => continue,
+            ":stack" => print_stack(exec.stack()),
+            ":altstack" => print_stack(exec.altstack()),
+            ":script" => println!("  {}", script.to_asm_string()),
+            ":reset" => {
+                script = ScriptBuf::new();
+                exec = new_exec(script.clone());
+            }
+            ":help" => println!("{}", HELP),
+            ":quit" => break,
+            _ if line.starts_with(':') => println!("unknown command, type :help for commands"),
+            _ => {
+                let new = match parse_line(line) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        println!("parse error: {}", e);
+                        continue;
+                    }
+                };
+                // Scripts are immutable, so we rerun everything with the new line
+                // appended and keep the old script if it fails.
+                let mut candidate = script.clone().into_bytes();
+                candidate.extend_from_slice(new.as_bytes());
+                match run(ScriptBuf::from_bytes(candidate)) {
+                    Ok(new_exec) => {
+                        script = new_exec.script().to_owned();
+                        exec = new_exec;
+                        print_stack(exec.stack());
+                    }
+                    Err(e) => println!("execution failed: {}, line ignored", e),
+                }
+            }
+        }
+    }
+}
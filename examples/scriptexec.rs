@@ -0,0 +1,158 @@
+//! A command-line script checker.
+//!
+//! Run with `cargo run --example scriptexec -- --help`. The process exits
+//! with 0 when the scripts are accepted, 1 when they are rejected and 2 on
+//! invalid arguments.
+
+use std::process::ExitCode;
+
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::ScriptBuf;
+use bitcoin_scriptexec::{Exec, ExecCtx, Options, TxTemplate};
+
+const USAGE: &str = "\
+usage: scriptexec [options]
+
+Executes scripts against a dummy transaction and reports whether they are
+accepted. Signature checks fail, as they can't commit to a real transaction.
+
+options:
+  --script-sig HEX     the scriptSig, executed first to build the initial stack
+                       of the scriptPubKey, only allowed in the legacy context
+  --script-pubkey HEX  the script to execute: the scriptPubKey in the legacy
+                       context, the witness script or the tapleaf script in the
+                       segwitv0 and tapscript contexts
+  --witness HEX        a witness item, repeat for several items starting with
+                       the bottom of the initial stack, use \"\" for empty items
+  --ctx CTX            legacy, segwitv0 or tapscript, defaults to legacy
+  --help               print this message
+
+exits with 0 on accept, 1 on reject and 2 on invalid arguments";
+
+struct Args {
+    script_sig: ScriptBuf,
+    script_pubkey: ScriptBuf,
+    witness: Vec<Vec<u8>>,
+    ctx: ExecCtx,
+}
+
+fn parse_hex(flag: &str, value: Option<String>) -> Result<Vec<u8>, String> {
+    let value = value.ok_or_else(|| format!("{} needs a value", flag))?;
+    Vec::from_hex(&value).map_err(|e| format!("{}: {}", flag, e))
+}
+
+/// Parses the arguments, returning [None] if the usage was asked for.
+fn parse_args() -> Result<Option<Args>, String> {
+    let mut args = Args {
+        script_sig: ScriptBuf::new(),
+        script_pubkey: ScriptBuf::new(),
+        witness: Vec::new(),
+        ctx: ExecCtx::Legacy,
+    };
+
+    let mut iter = std::env::args().skip(1);
+    while let Some(flag) = iter.next() {
+        match flag.as_str() {
+            "--script-sig" => args.script_sig = parse_hex(&flag, iter.next())?.into(),
+            "--script-pubkey" => args.script_pubkey = parse_hex(&flag, iter.next())?.into(),
+            "--witness" => args.witness.push(parse_hex(&flag, iter.next())?),
+            "--ctx" => {
+                args.ctx = match iter.next().as_deref() {
+                    Some("legacy") => ExecCtx::Legacy,
+                    Some("segwitv0") => ExecCtx::SegwitV0,
+                    Some("tapscript") => ExecCtx::Tapscript,
+                    _ => return Err("--ctx must be legacy, segwitv0 or tapscript".into()),
+                }
+            }
+            "--help" | "-h" => return Ok(None),
+            _ => return Err(format!("unknown argument: {}", flag)),
+        }
+    }
+
+    if args.ctx != ExecCtx::Legacy && !args.script_sig.is_empty() {
+        return Err("--script-sig is only allowed in the legacy context".into());
+    }
+    if args.ctx == ExecCtx::Legacy && !args.witness.is_empty() {
+        return Err("--witness is not allowed in the legacy context".into());
+    }
+    Ok(Some(args))
+}
+
+/// Runs [script] on [stack] and returns the final stack if it was accepted,
+/// printing a report either way.
+///
+/// Without [final_check], the final stack doesn't need to pass the final
+/// stack check of [ctx], as for the scriptSig.
+fn run(
+    name: &str,
+    ctx: ExecCtx,
+    script: ScriptBuf,
+    stack: Vec<Vec<u8>>,
+    final_check: bool,
+) -> Option<Vec<Vec<u8>>> {
+    println!("{}: {}", name, script.to_asm_string());
+    let mut exec = match Exec::new(ctx, Options::default(), TxTemplate::dummy(), script, stack) {
+        Ok(exec) => exec,
+        Err(e) => {
+            println!("  rejected: {:?}", e);
+            return None;
+        }
+    };
+
+    // The position of the instruction that failed.
+    let mut position;
+    loop {
+        position = exec.script_position();
+        if exec.exec_next().is_err() {
+            break;
+        }
+    }
+    let res = exec.result().unwrap();
+
+    println!("  final stack:");
+    for item in res.final_stack.iter_str().rev() {
+        println!("    {}", item.as_hex());
+    }
+    match (&res.error, res.opcode) {
+        (Some(err), Some(op)) => {
+            println!("  rejected: {:?} at {} (position {})", err, op, position)
+        }
+        (Some(err), None) => println!("  rejected: {:?} (position {})", err, position),
+        (None, _) if final_check && !res.success => {
+            println!("  rejected: final stack check failed")
+        }
+        (None, _) => {
+            println!("  accepted");
+            return Some(res.final_stack.iter_str().collect());
+        }
+    }
+    None
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let stack = if args.ctx == ExecCtx::Legacy {
+        match run("scriptSig", ExecCtx::Legacy, args.script_sig, vec![], false) {
+            Some(stack) => stack,
+            None => return ExitCode::FAILURE,
+        }
+    } else {
+        args.witness
+    };
+
+    match run("scriptPubKey", args.ctx, args.script_pubkey, stack, true) {
+        Some(_) => ExitCode::SUCCESS,
+        None => ExitCode::FAILURE,
+    }
+}
@@ -0,0 +1,84 @@
+//! Feeds arbitrary bytes to the interpreter as a script plus initial stack.
+//!
+//! The input is laid out as follows:
+//! - 1 byte selecting the execution context
+//! - 1 byte with the number of initial stack items, each of which is
+//!   prefixed with a length byte
+//! - the remainder is the script
+
+#![no_main]
+
+use bitcoin::hashes::Hash;
+use bitcoin::taproot::TapLeafHash;
+use bitcoin::{
+    absolute, transaction, Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+};
+use bitcoin_scriptexec::{Exec, ExecCtx, Options, TxTemplate};
+use libfuzzer_sys::fuzz_target;
+
+fn split_input(mut data: &[u8]) -> Option<(ExecCtx, Vec<Vec<u8>>, &[u8])> {
+    let (&ctx, rest) = data.split_first()?;
+    let ctx = match ctx % 3 {
+        0 => ExecCtx::Legacy,
+        1 => ExecCtx::SegwitV0,
+        _ => ExecCtx::Tapscript,
+    };
+    let (&nb_items, rest) = rest.split_first()?;
+    data = rest;
+
+    let mut stack = Vec::with_capacity(nb_items as usize);
+    for _ in 0..nb_items {
+        let (&len, rest) = data.split_first()?;
+        if rest.len() < len as usize {
+            return None;
+        }
+        let (item, rest) = rest.split_at(len as usize);
+        stack.push(item.to_vec());
+        data = rest;
+    }
+
+    Some((ctx, stack, data))
+}
+
+fuzz_target!(|data: &[u8]| {
+    let (ctx, stack, script) = match split_input(data) {
+        Some(v) => v,
+        None => return,
+    };
+
+    let tx = TxTemplate {
+        tx: Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ZERO,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::ZERO,
+                script_pubkey: ScriptBuf::new(),
+            }],
+        },
+        prevouts: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: ScriptBuf::new(),
+        }],
+        input_idx: 0,
+        taproot_annex_scriptleaf: Some((TapLeafHash::all_zeros(), None)),
+    };
+
+    let mut exec = match Exec::new(
+        ctx,
+        Options::default(),
+        tx,
+        ScriptBuf::from_bytes(script.to_vec()),
+        stack,
+    ) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    while exec.exec_next().is_ok() {}
+    assert!(exec.result().is_some());
+});
@@ -0,0 +1,738 @@
+use bitcoin::opcodes::{all::*, Opcode};
+use bitcoin::script::{Instruction, Script};
+
+use crate::{
+    disabled_opcode_error, read_scriptint, utils, ExecCtx, ExecError, Options,
+    MAX_SCRIPT_ELEMENT_SIZE,
+};
+
+/// A problem found by [analyze_errors], or an advisory about a construct
+/// that may not behave as expected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptIssue {
+    /// Executing the instruction at byte offset [position] fails with [error].
+    ///
+    /// [opcode] is [None] for issues found at the end of the script.
+    Error {
+        position: usize,
+        opcode: Option<Opcode>,
+        error: ExecError,
+    },
+    /// The stack depth can't be followed past the instruction at [position],
+    /// so stack underflows after it are no longer reported.
+    GaveUp { position: usize, opcode: Opcode },
+    /// An advisory for the OP_CODESEPARATOR at [position], which doesn't fail.
+    ///
+    /// Its effect depends on the context: in Tapscript it only changes the
+    /// position signatures after it commit to, while in legacy and segwit v0
+    /// scripts it removes the code before it from the signed script code.
+    CodeSeparator { position: usize },
+}
+
+/// The number of elements an opcode pops and pushes in Tapscript, or [None]
+/// if it depends on the values on the stack.
+///
+/// Opcodes that only inspect elements, like OP_CSV, pop and push them back.
+pub(crate) fn stack_effect(op: Opcode) -> Option<(usize, usize)> {
+    Some(match op {
+        OP_NOP | OP_NOP1 | OP_NOP4 | OP_NOP5 | OP_NOP6 | OP_NOP7 | OP_NOP8 | OP_NOP9 | OP_NOP10
+        | OP_ELSE | OP_ENDIF | OP_RETURN | OP_CODESEPARATOR => (0, 0),
+        OP_CLTV | OP_CSV => (1, 1),
+        OP_IF | OP_NOTIF | OP_VERIFY | OP_DROP | OP_TOALTSTACK => (1, 0),
+        OP_FROMALTSTACK => (0, 1),
+        OP_2DROP => (2, 0),
+        OP_2DUP => (2, 4),
+        OP_3DUP => (3, 6),
+        OP_2OVER => (4, 6),
+        OP_2ROT => (6, 6),
+        OP_2SWAP => (4, 4),
+        // OP_IFDUP only pushes for true elements, assume the worst.
+        OP_IFDUP => (1, 1),
+        OP_DEPTH => (0, 1),
+        OP_DUP => (1, 2),
+        OP_NIP => (2, 1),
+        OP_OVER => (2, 3),
+        // The depth reached by OP_PICK and OP_ROLL is checked separately.
+        OP_PICK => (1, 1),
+        OP_ROLL => (1, 0),
+        OP_ROT => (3, 3),
+        OP_SWAP => (2, 2),
+        OP_TUCK => (2, 3),
+        OP_SIZE => (1, 2),
+        OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL | OP_RIPEMD160 | OP_SHA1
+        | OP_SHA256 | OP_HASH160 | OP_HASH256 => (1, 1),
+        OP_CAT
+        | OP_EQUAL
+        | OP_ADD
+        | OP_SUB
+        | OP_MUL
+        | OP_DIV
+        | OP_BOOLAND
+        | OP_BOOLOR
+        | OP_NUMEQUAL
+        | OP_NUMNOTEQUAL
+        | OP_LESSTHAN
+        | OP_GREATERTHAN
+        | OP_LESSTHANOREQUAL
+        | OP_GREATERTHANOREQUAL
+        | OP_MIN
+        | OP_MAX
+        | OP_CHECKSIG => (2, 1),
+        OP_EQUALVERIFY | OP_NUMEQUALVERIFY | OP_CHECKSIGVERIFY => (2, 0),
+        OP_WITHIN | OP_CHECKSIGADD => (3, 1),
+        _ => return None,
+    })
+}
+
+/// Whether [op] fails when executed in Tapscript with [opt], whatever the stack.
+///
+/// Disabled opcodes are checked like the executor does. Opcodes without a
+/// known stack effect are exactly the ones the executor fails with BadOpcode.
+fn opcode_error(op: Opcode, opt: &Options) -> Option<ExecError> {
+    if let Some(err) = disabled_opcode_error(op, ExecCtx::Tapscript, opt) {
+        return Some(err);
+    }
+    match op {
+        OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => Some(ExecError::TapscriptCheckMultiSig),
+        _ if stack_effect(op).is_none() => Some(ExecError::BadOpcode),
+        _ => None,
+    }
+}
+
+/// The stack depths when entering an IF and at the end of its branches.
+struct Branch {
+    depth: Option<(usize, usize)>,
+    ended: Option<Option<(usize, usize)>>,
+}
+
+/// Lists the problems [script] would run into when executed in Tapscript
+/// with an empty initial stack and the default [crate::Options].
+///
+/// This is a static pass over the script, so unlike execution it doesn't stop
+/// at the first problem. It doesn't know which branches would be taken, so
+/// problems are reported in all branches, and stack depths are followed
+/// assuming the branch leaving the fewest elements is taken. A stack underflow
+/// is reported once, after which the missing elements are assumed to be there.
+///
+/// When the stack depth depends on values on the stack, it can no longer be
+/// followed and a [ScriptIssue::GaveUp] is recorded. Parsing stops at the
+/// first instruction that doesn't parse.
+pub fn analyze_errors(script: &Script) -> Vec<ScriptIssue> {
+    let opt = Options::default();
+    let mut issues = Vec::new();
+    // The stack and altstack depths, or [None] when we gave up following them.
+    let mut depth = Some((0usize, 0usize));
+    let mut branches: Vec<Branch> = Vec::new();
+    // The number pushed by the previous instruction, for OP_PICK and OP_ROLL.
+    let mut last_num: Option<i64> = None;
+
+    let error = |issues: &mut Vec<ScriptIssue>, position, opcode, error| {
+        issues.push(ScriptIssue::Error {
+            position,
+            opcode,
+            error,
+        })
+    };
+
+    for res in script.instruction_indices() {
+        let (position, ins) = match res {
+            Ok(i) => i,
+            Err(_) => {
+                // The position of the bad instruction isn't known, only
+                // that it follows the last one that parsed.
+                error(&mut issues, script.len(), None, ExecError::BadOpcode);
+                return issues;
+            }
+        };
+        let op = match ins {
+            Instruction::PushBytes(p) => {
+                let op = Opcode::from(script.as_bytes()[position]);
+                if p.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                    error(&mut issues, position, Some(op), ExecError::PushSize);
+                } else if !utils::check_minimal_push(p.as_bytes(), op) {
+                    error(&mut issues, position, Some(op), ExecError::MinimalData);
+                }
+                last_num = read_scriptint(p.as_bytes(), 4, true).ok();
+                if let Some((ref mut main, _)) = depth {
+                    *main += 1;
+                }
+                continue;
+            }
+            Instruction::Op(op) => op,
+        };
+
+        if op.to_u8() >= OP_PUSHNUM_NEG1.to_u8()
+            && op.to_u8() <= OP_PUSHNUM_16.to_u8()
+            && op != OP_RESERVED
+        {
+            last_num = Some(op.to_u8() as i64 - OP_PUSHNUM_1.to_u8() as i64 + 1);
+            if let Some((ref mut main, _)) = depth {
+                *main += 1;
+            }
+            continue;
+        }
+        let pushed = last_num.take();
+
+        if let Some(err) = opcode_error(op, &opt) {
+            error(&mut issues, position, Some(op), err);
+        }
+        if op == OP_CODESEPARATOR {
+            issues.push(ScriptIssue::CodeSeparator { position });
+        }
+
+        if let Some((ref mut main, ref mut alt)) = depth {
+            if op == OP_TOALTSTACK {
+                *alt += 1;
+            }
+            if op == OP_FROMALTSTACK {
+                if *alt == 0 {
+                    error(
+                        &mut issues,
+                        position,
+                        Some(op),
+                        ExecError::InvalidStackOperation,
+                    );
+                } else {
+                    *alt -= 1;
+                }
+            }
+
+            if let Some((pops, pushes)) = stack_effect(op) {
+                // OP_PICK and OP_ROLL also need the n + 1 elements below n.
+                let needed = match (op, pushed) {
+                    (OP_PICK | OP_ROLL, Some(n)) if n >= 0 => pops + n as usize + 1,
+                    _ => pops,
+                };
+                if *main < needed {
+                    error(
+                        &mut issues,
+                        position,
+                        Some(op),
+                        ExecError::InvalidStackOperation,
+                    );
+                    *main = needed;
+                }
+                *main = *main - pops + pushes;
+            } else if op == OP_CHECKMULTISIG || op == OP_CHECKMULTISIGVERIFY {
+                issues.push(ScriptIssue::GaveUp {
+                    position,
+                    opcode: op,
+                });
+                depth = None;
+            }
+        }
+
+        match op {
+            OP_IF | OP_NOTIF => branches.push(Branch { depth, ended: None }),
+            OP_ELSE => match branches.last_mut() {
+                Some(branch) => {
+                    // Follow the branch ending with the fewest elements.
+                    branch.ended = Some(match branch.ended {
+                        Some(end) => min_depth(end, depth),
+                        None => depth,
+                    });
+                    depth = branch.depth;
+                }
+                None => error(
+                    &mut issues,
+                    position,
+                    Some(op),
+                    ExecError::UnbalancedConditional,
+                ),
+            },
+            OP_ENDIF => match branches.pop() {
+                // Without OP_ELSE, the branch can also be skipped.
+                Some(branch) => depth = min_depth(branch.ended.unwrap_or(branch.depth), depth),
+                None => error(
+                    &mut issues,
+                    position,
+                    Some(op),
+                    ExecError::UnbalancedConditional,
+                ),
+            },
+            _ => {}
+        }
+    }
+
+    if !branches.is_empty() {
+        error(
+            &mut issues,
+            script.len(),
+            None,
+            ExecError::UnbalancedConditional,
+        );
+    }
+    issues
+}
+
+/// The smallest of two stack depths, [None] if either of them isn't known.
+fn min_depth(a: Option<(usize, usize)>, b: Option<(usize, usize)>) -> Option<(usize, usize)> {
+    let ((m1, a1), (m2, a2)) = (a?, b?);
+    Some((m1.min(m2), a1.min(a2)))
+}
+
+/// An OP_FROMALTSTACK at byte offset [position] that finds the altstack empty,
+/// which no initial stack can avoid, see [min_stack_depth].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnderflowAt {
+    pub position: usize,
+    pub opcode: Opcode,
+}
+
+/// Returns the number of elements the initial stack needs for [script] to
+/// execute in Tapscript without a stack underflow.
+///
+/// This is a static pass over the script. For scripts without conditionals
+/// the result is exact. The branches of a conditional are each followed from
+/// the depth at the IF, so the result covers whichever branch is taken, but
+/// after the ENDIF the branch leaving the fewest elements is assumed, which can
+/// overestimate what later opcodes need. OP_PICK and OP_ROLL are only followed
+/// precisely when their operand is pushed right before them.
+///
+/// Following stops at the first opcode whose stack effect depends on the
+/// values on the stack, like OP_CHECKMULTISIG, or that doesn't parse, and the
+/// depth needed by the opcodes before it is returned.
+pub fn min_stack_depth(script: &Script) -> Result<usize, UnderflowAt> {
+    // The stack depth relative to the initial one, and the altstack depth.
+    type Depths = (i64, usize);
+    let mut depth = 0i64;
+    let mut alt = 0usize;
+    let mut needed = 0i64;
+    // The depths when entering each conditional and at the end of its
+    // finished branches.
+    let mut branches: Vec<(Depths, Option<Depths>)> = Vec::new();
+    let mut last_num: Option<i64> = None;
+
+    for res in script.instruction_indices() {
+        let Ok((position, ins)) = res else { break };
+        let op = match ins {
+            Instruction::PushBytes(p) => {
+                last_num = read_scriptint(p.as_bytes(), 4, true).ok();
+                depth += 1;
+                continue;
+            }
+            Instruction::Op(op) => op,
+        };
+        if op.to_u8() >= OP_PUSHNUM_NEG1.to_u8()
+            && op.to_u8() <= OP_PUSHNUM_16.to_u8()
+            && op != OP_RESERVED
+        {
+            last_num = Some(op.to_u8() as i64 - OP_PUSHNUM_1.to_u8() as i64 + 1);
+            depth += 1;
+            continue;
+        }
+        let pushed = last_num.take();
+
+        let Some((pops, pushes)) = stack_effect(op) else {
+            break;
+        };
+        let reached = match (op, pushed) {
+            (OP_PICK | OP_ROLL, Some(n)) if n >= 0 => pops as i64 + n + 1,
+            _ => pops as i64,
+        };
+        needed = needed.max(reached - depth);
+        depth += pushes as i64 - pops as i64;
+
+        match op {
+            OP_TOALTSTACK => alt += 1,
+            OP_FROMALTSTACK => {
+                alt = alt.checked_sub(1).ok_or(UnderflowAt {
+                    position,
+                    opcode: op,
+                })?;
+            }
+            OP_IF | OP_NOTIF => branches.push(((depth, alt), None)),
+            OP_ELSE => {
+                if let Some((start, ended)) = branches.last_mut() {
+                    let end = (depth, alt);
+                    *ended = Some(ended.map_or(end, |e| (e.0.min(end.0), e.1.min(end.1))));
+                    (depth, alt) = *start;
+                }
+            }
+            OP_ENDIF => {
+                if let Some((start, ended)) = branches.pop() {
+                    // Without OP_ELSE, the branch can also be skipped.
+                    let other = ended.unwrap_or(start);
+                    depth = depth.min(other.0);
+                    alt = alt.min(other.1);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(needed as usize)
+}
+
+/// The output template of a script pubkey, see [is_standard].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StandardnessResult {
+    P2pk,
+    P2pkh,
+    P2sh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+    /// A witness program of an unknown version or length.
+    WitnessUnknown,
+    /// A bare m-of-n multisig with at most 3 keys.
+    BareMultisig {
+        required: u8,
+        keys: u8,
+    },
+    /// An OP_RETURN output carrying at most 80 bytes of data.
+    NullData,
+    NonStandard,
+}
+
+impl StandardnessResult {
+    pub fn is_standard(&self) -> bool {
+        *self != StandardnessResult::NonStandard
+    }
+}
+
+/// Maximum size of a standard OP_RETURN script pubkey, including the OP_RETURN
+/// and push opcodes.
+const MAX_OP_RETURN_RELAY: usize = 83;
+
+/// Maximum number of keys in a standard bare multisig.
+const MAX_BARE_MULTISIG_KEYS: u8 = 3;
+
+/// Classifies [script_pubkey] like Bitcoin Core's mempool policy does for
+/// transaction outputs.
+///
+/// This only looks at the script itself: policies on the output value, like
+/// the dust limit, and on the spending inputs are not checked.
+pub fn is_standard(script_pubkey: &Script) -> StandardnessResult {
+    if script_pubkey.is_p2pkh() {
+        StandardnessResult::P2pkh
+    } else if script_pubkey.is_p2sh() {
+        StandardnessResult::P2sh
+    } else if script_pubkey.is_p2wpkh() {
+        StandardnessResult::P2wpkh
+    } else if script_pubkey.is_p2wsh() {
+        StandardnessResult::P2wsh
+    } else if script_pubkey.is_p2tr() {
+        StandardnessResult::P2tr
+    } else if script_pubkey.is_witness_program() {
+        StandardnessResult::WitnessUnknown
+    } else if script_pubkey.is_p2pk() {
+        StandardnessResult::P2pk
+    } else if script_pubkey.is_op_return() {
+        if script_pubkey.len() <= MAX_OP_RETURN_RELAY
+            && script_pubkey[1..].is_push_only()
+            && script_pubkey.instructions().all(|i| i.is_ok())
+        {
+            StandardnessResult::NullData
+        } else {
+            StandardnessResult::NonStandard
+        }
+    } else {
+        match bare_multisig(script_pubkey) {
+            Some((required, keys)) if keys <= MAX_BARE_MULTISIG_KEYS => {
+                StandardnessResult::BareMultisig { required, keys }
+            }
+            _ => StandardnessResult::NonStandard,
+        }
+    }
+}
+
+/// Parses `m <pubkey>... n OP_CHECKMULTISIG` with 1 <= m <= n <= 16 and
+/// compressed or uncompressed keys, returning m and n.
+fn bare_multisig(script: &Script) -> Option<(u8, u8)> {
+    let ins = script
+        .instructions_minimal()
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    let [Instruction::Op(first), keys @ .., Instruction::Op(last), Instruction::Op(OP_CHECKMULTISIG)] =
+        ins.as_slice()
+    else {
+        return None;
+    };
+    let required = decode_pushnum(*first)?;
+    let nb_keys = decode_pushnum(*last)?;
+    let all_keys = keys
+        .iter()
+        .all(|k| matches!(k, Instruction::PushBytes(p) if p.len() == 33 || p.len() == 65));
+    if !all_keys || keys.len() != nb_keys as usize || required > nb_keys {
+        return None;
+    }
+    Some((required, nb_keys))
+}
+
+/// The number pushed by OP_1 to OP_16.
+fn decode_pushnum(op: Opcode) -> Option<u8> {
+    let n = op.to_u8().checked_sub(OP_PUSHNUM_1.to_u8())?;
+    (n < 16).then_some(n + 1)
+}
+
+/// Returns whether [script] can never be executed successfully, for the
+/// simple cases that can be told without executing it.
+///
+/// This is a heuristic, not a solver: a [false] result doesn't mean the
+/// script can be satisfied. A script is reported as provably false if:
+/// - it is larger than 10,000 bytes or can't be parsed,
+/// - it contains a disabled opcode, OP_VERIF or OP_VERNOTIF, which fail even
+///   in unexecuted branches,
+/// - its conditionals are unbalanced,
+/// - it has an OP_RETURN outside of any conditional, or
+/// - it ends with a push of a false value outside of any conditional.
+///
+/// This assumes the script is not executed in Tapscript, where some of these
+/// opcodes are OP_SUCCESS opcodes making the script succeed.
+pub fn is_provably_false(script: &Script) -> bool {
+    if script.len() > crate::MAX_SCRIPT_SIZE {
+        return true;
+    }
+    let mut depth = 0usize;
+    let mut last_false = false;
+    for ins in script.instructions() {
+        let Ok(ins) = ins else { return true };
+        last_false = false;
+        match ins {
+            Instruction::PushBytes(p) => {
+                last_false = depth == 0 && !bitcoin::script::read_scriptbool(p.as_bytes());
+            }
+            Instruction::Op(op) => match op {
+                OP_CAT | OP_SUBSTR | OP_LEFT | OP_RIGHT | OP_INVERT | OP_AND | OP_OR | OP_XOR
+                | OP_2MUL | OP_2DIV | OP_MUL | OP_DIV | OP_MOD | OP_LSHIFT | OP_RSHIFT
+                | OP_VERIF | OP_VERNOTIF => return true,
+                OP_RETURN if depth == 0 => return true,
+                OP_IF | OP_NOTIF => depth += 1,
+                OP_ELSE if depth == 0 => return true,
+                OP_ENDIF => match depth.checked_sub(1) {
+                    Some(d) => depth = d,
+                    None => return true,
+                },
+                _ => {}
+            },
+        }
+    }
+    depth != 0 || last_false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Exec, TxTemplate};
+    use bitcoin::script::Builder;
+
+    #[test]
+    fn reports_every_problem() {
+        // OP_ADD underflows and OP_MUL is disabled by default.
+        let script = Builder::new()
+            .push_opcode(OP_ADD)
+            .push_int(2)
+            .push_opcode(OP_MUL)
+            .into_script();
+        assert_eq!(
+            analyze_errors(&script),
+            vec![
+                ScriptIssue::Error {
+                    position: 0,
+                    opcode: Some(OP_ADD),
+                    error: ExecError::InvalidStackOperation,
+                },
+                ScriptIssue::Error {
+                    position: 2,
+                    opcode: Some(OP_MUL),
+                    error: ExecError::DisabledOpcode,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn opcode_errors_match_execution() {
+        for op in [
+            OP_CAT,
+            OP_MUL,
+            OP_SUBSTR,
+            OP_XOR,
+            OP_RESERVED,
+            OP_VER,
+            OP_ADD,
+        ] {
+            let script = Builder::new()
+                .push_int(3)
+                .push_int(4)
+                .push_opcode(op)
+                .into_script();
+            let mut exec = Exec::new(
+                ExecCtx::Tapscript,
+                Options::default(),
+                TxTemplate::dummy(),
+                script.clone(),
+                vec![],
+            )
+            .unwrap();
+            while exec.exec_next().is_ok() {}
+            let executed = exec.result().unwrap().error.clone();
+
+            let reported = analyze_errors(&script).into_iter().find_map(|i| match i {
+                ScriptIssue::Error { error, .. } => Some(error),
+                _ => None,
+            });
+            assert_eq!(reported, executed, "{}", op);
+        }
+    }
+
+    #[test]
+    fn standard_templates() {
+        use bitcoin::hashes::Hash;
+        use bitcoin::ScriptBuf;
+        use StandardnessResult::*;
+
+        let key = [2; 33];
+        let data = |len| bitcoin::script::PushBytesBuf::try_from(vec![7; len]).unwrap();
+        let witness_program = |version, len| {
+            Builder::new()
+                .push_opcode(version)
+                .push_slice(data(len))
+                .into_script()
+        };
+        let multisig = |required, keys| {
+            let mut builder = Builder::new().push_int(required);
+            for _ in 0..keys {
+                builder = builder.push_slice(key);
+            }
+            builder
+                .push_int(keys)
+                .push_opcode(OP_CHECKMULTISIG)
+                .into_script()
+        };
+        let cases = [
+            (
+                Builder::new()
+                    .push_slice(key)
+                    .push_opcode(OP_CHECKSIG)
+                    .into_script(),
+                P2pk,
+            ),
+            (ScriptBuf::new_p2pkh(&Hash::all_zeros()), P2pkh),
+            (ScriptBuf::new_p2sh(&Hash::all_zeros()), P2sh),
+            (ScriptBuf::new_p2wpkh(&Hash::all_zeros()), P2wpkh),
+            (ScriptBuf::new_p2wsh(&Hash::all_zeros()), P2wsh),
+            (witness_program(OP_PUSHNUM_1, 32), P2tr),
+            (witness_program(OP_PUSHNUM_2, 20), WitnessUnknown),
+            (
+                multisig(1, 3),
+                BareMultisig {
+                    required: 1,
+                    keys: 3,
+                },
+            ),
+            (multisig(1, 4), NonStandard),
+            (multisig(3, 2), NonStandard),
+            (ScriptBuf::new_op_return(data(80)), NullData),
+            (ScriptBuf::new_op_return(data(81)), NonStandard),
+            (
+                Builder::new()
+                    .push_opcode(OP_PUSHNUM_1)
+                    .push_opcode(OP_ADD)
+                    .into_script(),
+                NonStandard,
+            ),
+        ];
+        for (script, expected) in cases {
+            assert_eq!(is_standard(&script), expected, "{}", script);
+            assert_eq!(is_standard(&script).is_standard(), expected != NonStandard);
+        }
+    }
+
+    #[test]
+    fn provably_false_scripts() {
+        let script = |ops: &[Opcode]| {
+            ops.iter()
+                .fold(Builder::new(), |b, op| b.push_opcode(*op))
+                .into_script()
+        };
+        assert!(is_provably_false(&script(&[OP_RETURN])));
+        assert!(is_provably_false(&script(&[OP_DUP, OP_DROP, OP_RETURN])));
+        assert!(is_provably_false(&script(&[OP_PUSHBYTES_0])));
+        assert!(is_provably_false(&script(&[OP_DROP, OP_PUSHBYTES_0])));
+        assert!(is_provably_false(&script(&[OP_IF, OP_CAT, OP_ENDIF])));
+        assert!(is_provably_false(&script(&[OP_IF])));
+
+        let p2pkh = bitcoin::ScriptBuf::new_p2pkh(&bitcoin::hashes::Hash::all_zeros());
+        assert!(!is_provably_false(&p2pkh));
+        // Conditional OP_RETURNs and false values don't make a script provably false.
+        assert!(!is_provably_false(&script(&[OP_IF, OP_RETURN, OP_ENDIF])));
+        assert!(!is_provably_false(&script(&[
+            OP_IF,
+            OP_PUSHBYTES_0,
+            OP_ELSE,
+            OP_PUSHNUM_1,
+            OP_ENDIF
+        ])));
+    }
+
+    #[test]
+    fn codeseparator_advisory() {
+        let script = Builder::new()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_CODESEPARATOR)
+            .push_opcode(OP_PUSHBYTES_0)
+            .push_opcode(OP_IF)
+            .push_opcode(OP_CODESEPARATOR)
+            .push_opcode(OP_ENDIF)
+            .into_script();
+        // Those in unexecuted branches are reported too.
+        assert_eq!(
+            analyze_errors(&script),
+            [
+                ScriptIssue::CodeSeparator { position: 1 },
+                ScriptIssue::CodeSeparator { position: 4 },
+            ]
+        );
+
+        let script = Builder::new().push_opcode(OP_PUSHNUM_1).into_script();
+        assert_eq!(analyze_errors(&script), []);
+    }
+
+    #[test]
+    fn min_stack_depths() {
+        let script = |ops: &[Opcode]| {
+            ops.iter()
+                .fold(Builder::new(), |b, op| b.push_opcode(*op))
+                .into_script()
+        };
+        let cases: [(&[Opcode], usize); 5] = [
+            (&[OP_PUSHNUM_1, OP_PUSHNUM_2, OP_ADD], 0),
+            (&[OP_DUP, OP_ADD, OP_ADD], 2),
+            (&[OP_ADD, OP_PUSHNUM_3, OP_SUB, OP_NUMEQUAL], 3),
+            (&[OP_PUSHNUM_1, OP_ROT], 2),
+            (&[OP_TOALTSTACK, OP_2DUP, OP_FROMALTSTACK], 3),
+        ];
+        for (ops, needed) in cases {
+            let script = script(ops);
+            assert_eq!(min_stack_depth(&script), Ok(needed), "{}", script);
+
+            // Exactly that many initial elements are needed to execute it.
+            for len in needed.saturating_sub(1)..=needed {
+                let tx = TxTemplate::dummy();
+                let mut exec = Exec::new(
+                    ExecCtx::Tapscript,
+                    Options::default(),
+                    tx,
+                    script.clone(),
+                    vec![vec![1]; len],
+                )
+                .unwrap();
+                while exec.exec_next().is_ok() {}
+                let error = &exec.result().unwrap().error;
+                let underflow = *error == Some(ExecError::InvalidStackOperation);
+                assert_eq!(underflow, len < needed, "{} with {}", script, len);
+            }
+        }
+
+        let script = script(&[OP_TOALTSTACK, OP_FROMALTSTACK, OP_FROMALTSTACK]);
+        assert_eq!(
+            min_stack_depth(&script),
+            Err(UnderflowAt {
+                position: 2,
+                opcode: OP_FROMALTSTACK,
+            })
+        );
+    }
+}
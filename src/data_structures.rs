@@ -1,4 +1,4 @@
-use crate::utils::scriptint_vec;
+use crate::utils::{read_scriptint_size, scriptint_arr, scriptint_vec};
 use crate::{read_scriptint, ExecError};
 use alloc::rc::Rc;
 use core::cell::RefCell;
@@ -12,6 +12,39 @@ pub enum StackEntry {
     StrRef(Rc<RefCell<Vec<u8>>>),
 }
 
+impl StackEntry {
+    /// The size in bytes of the entry in its serialized form.
+    pub fn byte_len(&self) -> usize {
+        match self {
+            StackEntry::Num(v) => scriptint_arr(*v).1,
+            StackEntry::StrRef(v) => v.borrow().len(),
+        }
+    }
+
+    /// Calls [f] with the serialized form of the entry, without copying byte strings.
+    pub fn with_bytes<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
+        match self {
+            StackEntry::Num(v) => {
+                let (buf, len) = scriptint_arr(*v);
+                f(&buf[..len])
+            }
+            StackEntry::StrRef(v) => f(&v.borrow()),
+        }
+    }
+}
+
+/// Iterator over the byte string representations of the stack entries.
+pub type StackStrIter<'a> = Map<Iter<'a, StackEntry>, fn(&StackEntry) -> Vec<u8>>;
+
+/// The script execution stack.
+///
+/// Cloning a stack copies the vector of entries, but not the byte strings
+/// themselves: [StackEntry::StrRef] entries are reference counted and shared
+/// between the clones. The cost of a clone is thus linear in the number of
+/// entries, not in their size.
+///
+/// For the same reason, a stack can't be sent to another thread, use
+/// [Stack::iter_str] and [Stack::from_u8_vec] to move its contents.
 #[derive(Clone, Eq, Debug, PartialEq)]
 pub struct Stack(Vec<StackEntry>);
 
@@ -24,6 +57,13 @@ impl Stack {
         self.0.is_empty()
     }
 
+    /// Takes a snapshot of the stack that shares all byte strings with it.
+    ///
+    /// This is the same as [Clone::clone], see the type-level docs for the cost.
+    pub fn snapshot(&self) -> Stack {
+        self.clone()
+    }
+
     pub fn last(&self) -> Result<Vec<u8>, ExecError> {
         self.topstr(-1)
     }
@@ -36,15 +76,58 @@ impl Stack {
         res
     }
 
+    /// Takes the byte strings of the stack, from bottom to top, like
+    /// collecting [Stack::iter_str] does.
+    ///
+    /// Byte strings that aren't shared with another stack are moved out
+    /// instead of cloned.
+    pub fn into_witness(self) -> Vec<Vec<u8>> {
+        self.0
+            .into_iter()
+            .map(|entry| match entry {
+                StackEntry::Num(v) => scriptint_vec(v),
+                StackEntry::StrRef(v) => match Rc::try_unwrap(v) {
+                    Ok(v) => v.into_inner(),
+                    Err(v) => v.borrow().to_vec(),
+                },
+            })
+            .collect()
+    }
+
+    /// Creates a stack of byte strings, the last one being the top.
+    pub fn from_slices(v: &[&[u8]]) -> Self {
+        v.iter().copied().collect()
+    }
+
+    /// Creates a stack from its entries, the last one being the top.
+    pub fn from_entries(entries: Vec<StackEntry>) -> Self {
+        Self(entries)
+    }
+
+    /// The entries of the stack, from bottom to top.
+    pub fn entries(&self) -> &[StackEntry] {
+        &self.0
+    }
+
+    /// Takes the entries of the stack without encoding numbers, see [Stack::entries].
+    pub fn into_entries(self) -> Vec<StackEntry> {
+        self.0
+    }
+
     pub fn top(&self, offset: isize) -> Result<&StackEntry, ExecError> {
         debug_assert!(offset < 0, "offsets should be < 0");
         self.0
             .len()
-            .checked_sub(offset.abs() as usize)
-            .and_then(|i| Some(&self.0[i]))
+            .checked_sub(offset.unsigned_abs())
+            .map(|i| &self.0[i])
             .ok_or(ExecError::InvalidStackOperation)
     }
 
+    /// The entry at [index] counted from the bottom of the stack.
+    pub fn at(&self, index: usize) -> Result<&StackEntry, ExecError> {
+        self.0.get(index).ok_or(ExecError::InvalidStackOperation)
+    }
+
     pub fn topstr(&self, offset: isize) -> Result<Vec<u8>, ExecError> {
         let entry = self.top(offset)?;
         match entry {
@@ -54,23 +137,64 @@ impl Stack {
     }
 
     pub fn topnum(&self, offset: isize, require_minimal: bool) -> Result<i64, ExecError> {
+        self.topnum_sized(offset, 4, require_minimal)
+    }
+
+    /// Like [Stack::topnum], but allowing numbers of up to [max_size] bytes instead of 4.
+    pub fn topnum_sized(
+        &self,
+        offset: isize,
+        max_size: usize,
+        require_minimal: bool,
+    ) -> Result<i64, ExecError> {
         let entry = self.top(offset)?;
         match entry {
             StackEntry::Num(v) => {
-                if *v <= i32::MAX as i64 {
+                // The largest magnitude encodable in sign-magnitude with max_size bytes.
+                let max = u64::MAX
+                    .checked_shr((64 - 8 * max_size.min(8) + 1) as u32)
+                    .unwrap_or(0);
+                if v.unsigned_abs() <= max {
                     Ok(*v)
                 } else {
                     Err(ExecError::ScriptIntNumericOverflow)
                 }
             }
-            StackEntry::StrRef(v) => Ok(read_scriptint(v.borrow().as_slice(), 4, require_minimal)?),
+            StackEntry::StrRef(v) => Ok(read_scriptint(
+                v.borrow().as_slice(),
+                max_size,
+                require_minimal,
+            )?),
         }
     }
 
+    /// Pushes a number without encoding it.
+    ///
+    /// Numbers are minimally encoded when read as bytes, so 0 reads as the
+    /// empty byte string and 1 as `[0x01]`. This makes `pushnum(0)` and
+    /// `pushnum(1)` the canonical false and true results of opcodes.
+    ///
+    /// Any number can be pushed, but it is read back like its encoding would
+    /// be: [Stack::topnum] and [Stack::popnum] fail with
+    /// [ExecError::ScriptIntNumericOverflow] for magnitudes above [i32::MAX],
+    /// use [Stack::topnum_sized] or [Stack::popnum64] to read wider numbers.
     pub fn pushnum(&mut self, num: i64) {
         self.0.push(StackEntry::Num(num));
     }
 
+    /// Pushes the minimal script encoding of [n] as a byte string.
+    ///
+    /// Unlike [Stack::pushnum], which stores the number as a [StackEntry::Num]
+    /// and only encodes it when it is read as bytes, this pushes the result of
+    /// [scriptint_vec] as a [StackEntry::StrRef], exactly like a script push of
+    /// those bytes would.
+    ///
+    /// Every [i64] can be pushed, [i64::MIN] taking 9 bytes.
+    pub fn push_minimal_num(&mut self, n: i64) {
+        let (buf, len) = scriptint_arr(n);
+        self.pushstr(&buf[..len]);
+    }
+
     pub fn pushstr(&mut self, v: &[u8]) {
         self.0
             .push(StackEntry::StrRef(Rc::new(RefCell::new(v.to_vec()))));
@@ -80,6 +204,13 @@ impl Stack {
         self.0.push(v);
     }
 
+    /// Replaces the top entry by [entry], like a pop followed by a push.
+    pub fn replace_top(&mut self, entry: StackEntry) -> Result<(), ExecError> {
+        let top = self.0.last_mut().ok_or(ExecError::InvalidStackOperation)?;
+        *top = entry;
+        Ok(())
+    }
+
     pub fn needn(&self, min_nb_items: usize) -> Result<(), ExecError> {
         if self.len() < min_nb_items {
             Err(ExecError::InvalidStackOperation)
@@ -111,7 +242,7 @@ impl Stack {
         let entry = self.0.pop().ok_or(ExecError::InvalidStackOperation)?;
         match entry {
             StackEntry::Num(v) => {
-                if v <= i32::MAX as i64 {
+                if v.unsigned_abs() <= i32::MAX as u64 {
                     Ok(v)
                 } else {
                     Err(ExecError::ScriptIntNumericOverflow)
@@ -121,15 +252,148 @@ impl Stack {
         }
     }
 
+    /// Pops a number like [Stack::popnum], but on failure returns the popped
+    /// bytes along with the reason they aren't a valid number, so that lenient
+    /// callers can carry on.
+    ///
+    /// Returns [None] if the stack is empty.
+    pub fn try_popnum(
+        &mut self,
+        require_minimal: bool,
+    ) -> Option<Result<i64, (Vec<u8>, ScriptIntError)>> {
+        let entry = self.0.pop()?;
+        Some(match entry {
+            StackEntry::Num(v) => {
+                if v.unsigned_abs() <= i32::MAX as u64 {
+                    Ok(v)
+                } else {
+                    Err((scriptint_vec(v), ScriptIntError::NumericOverflow))
+                }
+            }
+            StackEntry::StrRef(v) => {
+                let v = v.borrow();
+                read_scriptint_size(&v, 4, require_minimal).map_err(|e| (v.to_vec(), e))
+            }
+        })
+    }
+
+    /// Pops a number of up to 8 bytes off the stack.
+    ///
+    /// This is not used by any consensus opcode, which all limit their
+    /// numeric operands to 4 bytes, but it allows prototyping 64-bit arithmetic.
+    pub fn popnum64(&mut self, require_minimal: bool) -> Result<i64, ExecError> {
+        let entry = self.0.pop().ok_or(ExecError::InvalidStackOperation)?;
+        match entry {
+            StackEntry::Num(v) => Ok(v),
+            StackEntry::StrRef(v) => Ok(read_scriptint(v.borrow().as_slice(), 8, require_minimal)?),
+        }
+    }
+
+    /// Pushes a number that can be read back with [Stack::popnum64].
+    ///
+    /// Fails for [i64::MIN], which has no 8-byte script encoding.
+    pub fn pushnum64(&mut self, num: i64) -> Result<(), ExecError> {
+        if num == i64::MIN {
+            return Err(ExecError::ScriptIntNumericOverflow);
+        }
+        self.0.push(StackEntry::Num(num));
+        Ok(())
+    }
+
+    // The stack-shuffling methods below behave like their opcode counterparts
+    // and move or clone the entries, so [StackEntry::StrRef] entries stay shared.
+
+    /// (x -- )
+    pub fn drop(&mut self) -> Result<(), ExecError> {
+        self.popn(1)
+    }
+
+    /// (x1 x2 -- )
+    pub fn drop2(&mut self) -> Result<(), ExecError> {
+        self.needn(2)?;
+        self.popn(2)
+    }
+
+    /// (x1 x2 -- x2)
+    pub fn nip(&mut self) -> Result<(), ExecError> {
+        self.needn(2)?;
+        let len = self.0.len();
+        self.0.remove(len - 2);
+        Ok(())
+    }
+
+    /// (x1 x2 -- x1 x2 x1)
+    pub fn over(&mut self) -> Result<(), ExecError> {
+        let x1 = self.top(-2)?.clone();
+        self.push(x1);
+        Ok(())
+    }
+
+    /// (x1 x2 -- x2 x1 x2)
+    pub fn tuck(&mut self) -> Result<(), ExecError> {
+        self.needn(2)?;
+        let x2 = self.top(-1)?.clone();
+        let len = self.0.len();
+        self.0.insert(len - 2, x2);
+        Ok(())
+    }
+
+    /// (x1 x2 -- x1 x2 x1 x2)
+    pub fn dup2(&mut self) -> Result<(), ExecError> {
+        self.needn(2)?;
+        let len = self.0.len();
+        self.0.extend_from_within(len - 2..);
+        Ok(())
+    }
+
+    /// (x1 x2 x3 -- x1 x2 x3 x1 x2 x3)
+    pub fn dup3(&mut self) -> Result<(), ExecError> {
+        self.needn(3)?;
+        let len = self.0.len();
+        self.0.extend_from_within(len - 3..);
+        Ok(())
+    }
+
+    /// (x1 x2 x3 x4 -- x3 x4 x1 x2)
+    pub fn swap2(&mut self) -> Result<(), ExecError> {
+        self.needn(4)?;
+        let len = self.0.len();
+        self.0[len - 4..].rotate_left(2);
+        Ok(())
+    }
+
+    /// (x1 x2 x3 x4 x5 x6 -- x3 x4 x5 x6 x1 x2)
+    pub fn rot2(&mut self) -> Result<(), ExecError> {
+        self.needn(6)?;
+        let len = self.0.len();
+        self.0[len - 6..].rotate_left(2);
+        Ok(())
+    }
+
     pub fn len(&self) -> usize {
         self.0.len()
     }
 
+    /// The number of entries the stack can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+
+    /// Removes all entries, keeping the allocated capacity for reuse.
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// The total size in bytes of all entries in their serialized form.
+    pub fn byte_size(&self) -> usize {
+        self.0.iter().map(StackEntry::byte_len).sum()
+    }
+
     pub fn remove(&mut self, v: usize) {
         self.0.remove(v);
     }
 
-    pub fn iter_str(&self) -> Map<Iter<StackEntry>, fn(&StackEntry) -> Vec<u8>> {
+    pub fn iter_str(&self) -> StackStrIter<'_> {
         self.0.iter().map(|v| match v {
             StackEntry::Num(v) => scriptint_vec(*v),
             StackEntry::StrRef(v) => v.borrow().to_vec(),
@@ -150,6 +414,37 @@ impl Default for Stack {
     }
 }
 
+impl From<&[&[u8]]> for Stack {
+    fn from(v: &[&[u8]]) -> Self {
+        Self::from_slices(v)
+    }
+}
+
+/// Pushes the byte strings in order, the last one being the top.
+impl<'a> FromIterator<&'a [u8]> for Stack {
+    fn from_iter<I: IntoIterator<Item = &'a [u8]>>(iter: I) -> Self {
+        let mut res = Self::new();
+        for v in iter {
+            res.pushstr(v);
+        }
+        res
+    }
+}
+
+/// Pushes the numbers in order as [StackEntry::Num], the last one being the top.
+///
+/// Like with [Stack::pushnum], any number can be pushed, and numbers too wide
+/// for the requested operand size fail when they are read.
+impl FromIterator<i64> for Stack {
+    fn from_iter<I: IntoIterator<Item = i64>>(iter: I) -> Self {
+        let mut res = Self::new();
+        for v in iter {
+            res.pushnum(v);
+        }
+        res
+    }
+}
+
 /// Ways parsing script integers might fail.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ScriptIntError {
@@ -158,6 +453,8 @@ pub enum ScriptIntError {
     NonMinimalPush,
     /// Tried to read an array off the stack as a number when it was more than 4 bytes.
     NumericOverflow,
+    /// The maximum size requested for reading a number exceeds 8 bytes.
+    MaxSizeTooLarge,
 }
 
 impl std::fmt::Display for ScriptIntError {
@@ -169,17 +466,278 @@ impl std::fmt::Display for ScriptIntError {
             NumericOverflow => {
                 f.write_str("numeric overflow (number on stack larger than 4 bytes)")
             }
+            MaxSizeTooLarge => f.write_str("maximum number size larger than 8 bytes"),
         }
     }
 }
 
-#[cfg(feature = "std")]
 impl std::error::Error for ScriptIntError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         use ScriptIntError::*;
 
         match *self {
-            NonMinimalPush | NumericOverflow => None,
+            NonMinimalPush | NumericOverflow | MaxSizeTooLarge => None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_minimal_num_pushes_bytes() {
+        let mut stack = Stack::new();
+        stack.push_minimal_num(-1);
+        stack.push_minimal_num(i64::MIN);
+        assert!(matches!(stack.entries()[0], StackEntry::StrRef(_)));
+        assert_eq!(stack.get(0), vec![0x81]);
+        assert_eq!(stack.get(1), vec![0, 0, 0, 0, 0, 0, 0, 0x80, 0x80]);
+        assert_eq!(stack.byte_size(), 10);
+    }
+
+    #[test]
+    fn collect_byte_strings() {
+        let stack: Stack = [&[1u8, 2][..], &[], &[3]].into_iter().collect();
+        assert_eq!(stack.len(), 3);
+        assert!(matches!(stack.entries()[1], StackEntry::StrRef(_)));
+        assert_eq!(
+            stack.iter_str().collect::<Vec<_>>(),
+            vec![vec![1, 2], vec![], vec![3]]
+        );
+        assert_eq!(Stack::from_slices(&[&[1, 2], &[], &[3]]), stack);
+        assert_eq!(Stack::from(&[&[1u8, 2][..], &[], &[3]][..]), stack);
+    }
+
+    #[test]
+    fn collect_numbers() {
+        let stack: Stack = [0, -1, i64::MAX].into_iter().collect();
+        let nums = [
+            StackEntry::Num(0),
+            StackEntry::Num(-1),
+            StackEntry::Num(i64::MAX),
+        ];
+        assert_eq!(stack.entries(), &nums);
+        assert_eq!(stack.byte_size(), 9);
+    }
+
+    #[test]
+    fn collect_numbers_accepts_i64_min() {
+        let stack: Stack = [1, i64::MIN].into_iter().collect();
+        assert_eq!(stack.entries()[1], StackEntry::Num(i64::MIN));
+        assert_eq!(
+            stack.topnum_sized(-1, 8, true),
+            Err(ExecError::ScriptIntNumericOverflow)
+        );
+        assert_eq!(stack.topstr(-1).unwrap().len(), 9);
+    }
+
+    #[test]
+    fn numbers_are_limited_to_4_bytes_both_ways() {
+        let max = i32::MAX as i64;
+        let mut stack: Stack = [-max - 1, max + 1, -max, max].into_iter().collect();
+        assert_eq!(stack.popnum(true), Ok(max));
+        assert_eq!(stack.popnum(true), Ok(-max));
+        assert_eq!(stack.popnum(true), Err(ExecError::ScriptIntNumericOverflow));
+        assert_eq!(
+            stack.topnum(-1, true),
+            Err(ExecError::ScriptIntNumericOverflow)
+        );
+        assert_eq!(stack.popnum(true), Err(ExecError::ScriptIntNumericOverflow));
+    }
+
+    #[test]
+    fn numbers_beyond_32_bits() {
+        let mut stack = Stack::new();
+        for n in [1 << 32, -(1 << 40), i64::MAX, -i64::MAX] {
+            stack.pushnum64(n).unwrap();
+            assert_eq!(stack.popnum64(true), Ok(n));
+        }
+        assert_eq!(
+            stack.pushnum64(i64::MIN),
+            Err(ExecError::ScriptIntNumericOverflow)
+        );
+
+        // 2^32 as a 5-byte string, readable as a 64-bit number only.
+        stack.pushstr(&[0, 0, 0, 0, 1]);
+        assert_eq!(
+            stack.topnum(-1, true),
+            Err(ExecError::ScriptIntNumericOverflow)
+        );
+        assert_eq!(stack.popnum64(true), Ok(1 << 32));
+        stack.pushstr(&[0xff; 8]);
+        assert_eq!(stack.popnum64(true), Ok(-i64::MAX));
+        stack.pushstr(&[1; 9]);
+        assert_eq!(
+            stack.popnum64(true),
+            Err(ExecError::ScriptIntNumericOverflow)
+        );
+    }
+
+    #[test]
+    fn snapshot_shares_byte_strings() {
+        let mut stack = Stack::from_slices(&[&[1, 2, 3], &[4]]);
+        stack.pushnum(5);
+        let snapshot = stack.snapshot();
+        assert_eq!(snapshot, stack);
+        for (a, b) in stack.entries().iter().zip(snapshot.entries()) {
+            match (a, b) {
+                (StackEntry::StrRef(a), StackEntry::StrRef(b)) => assert!(Rc::ptr_eq(a, b)),
+                (StackEntry::Num(a), StackEntry::Num(b)) => assert_eq!(a, b),
+                _ => panic!("entry kinds differ"),
+            }
+        }
+    }
+
+    #[test]
+    fn at_indexes_from_the_bottom() {
+        let mut stack: Stack = [1, 2].into_iter().collect();
+        stack.pushstr(b"top");
+        assert_eq!(stack.at(0), Ok(&StackEntry::Num(1)));
+        assert_eq!(stack.at(1), Ok(&StackEntry::Num(2)));
+        assert_eq!(stack.at(2), stack.top(-1));
+        assert_eq!(stack.at(3), Err(ExecError::InvalidStackOperation));
+    }
+
+    #[test]
+    fn shuffling_methods() {
+        type Op = fn(&mut Stack) -> Result<(), ExecError>;
+        let cases: [(Op, usize, &[i64]); 9] = [
+            (Stack::drop, 1, &[1, 2, 3, 4, 5]),
+            (Stack::drop2, 2, &[1, 2, 3, 4]),
+            (Stack::nip, 2, &[1, 2, 3, 4, 6]),
+            (Stack::over, 2, &[1, 2, 3, 4, 5, 6, 5]),
+            (Stack::tuck, 2, &[1, 2, 3, 4, 6, 5, 6]),
+            (Stack::dup2, 2, &[1, 2, 3, 4, 5, 6, 5, 6]),
+            (Stack::dup3, 3, &[1, 2, 3, 4, 5, 6, 4, 5, 6]),
+            (Stack::swap2, 4, &[1, 2, 5, 6, 3, 4]),
+            (Stack::rot2, 6, &[3, 4, 5, 6, 1, 2]),
+        ];
+        for (i, (op, depth, expected)) in cases.into_iter().enumerate() {
+            let mut stack: Stack = (1..=6).collect();
+            op(&mut stack).unwrap();
+            assert_eq!(stack, expected.iter().copied().collect(), "case {}", i);
+
+            let mut short: Stack = (1..depth as i64).collect();
+            let before = short.clone();
+            assert_eq!(op(&mut short), Err(ExecError::InvalidStackOperation));
+            assert_eq!(short, before, "case {}", i);
+        }
+
+        // Copies share the byte string they were made from.
+        let mut stack = Stack::new();
+        stack.pushstr(b"a");
+        stack.pushstr(b"b");
+        stack.over().unwrap();
+        stack.tuck().unwrap();
+        let [StackEntry::StrRef(a), StackEntry::StrRef(tucked), _, StackEntry::StrRef(top)] =
+            stack.entries()
+        else {
+            panic!("unexpected stack: {:?}", stack);
+        };
+        assert!(Rc::ptr_eq(a, tucked));
+        assert!(Rc::ptr_eq(a, top));
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let mut stack: Stack = [3].into_iter().collect();
+        stack.pushstr(b"abc");
+        let copy = stack.snapshot();
+
+        let entries = stack.into_entries();
+        assert_eq!(entries, copy.entries());
+        let restored = Stack::from_entries(entries);
+        assert_eq!(restored, copy);
+        assert_eq!(restored.entries()[0], StackEntry::Num(3));
+        let (StackEntry::StrRef(a), StackEntry::StrRef(b)) =
+            (&restored.entries()[1], &copy.entries()[1])
+        else {
+            panic!("unexpected stack: {:?}", restored);
+        };
+        assert!(Rc::ptr_eq(a, b));
+    }
+
+    #[test]
+    fn clear_keeps_capacity() {
+        let mut stack: Stack = (0..2000).collect();
+        let capacity = stack.capacity();
+        assert!(capacity >= 2000);
+        stack.clear();
+        assert_eq!(stack.len(), 0);
+        assert_eq!(stack.capacity(), capacity);
+    }
+
+    #[test]
+    fn replace_top_entry() {
+        let mut stack = Stack::new();
+        assert_eq!(
+            stack.replace_top(StackEntry::Num(1)),
+            Err(ExecError::InvalidStackOperation)
+        );
+        stack.pushstr(b"a");
+        stack.replace_top(StackEntry::Num(7)).unwrap();
+        assert_eq!(stack.len(), 1);
+        assert_eq!(stack.top(-1), Ok(&StackEntry::Num(7)));
+    }
+
+    #[test]
+    fn large_host_numbers() {
+        let mut stack = Stack::new();
+        stack.pushnum(i64::MAX);
+        assert_eq!(
+            stack.topnum(-1, true),
+            Err(ExecError::ScriptIntNumericOverflow)
+        );
+        // Reading it as an 8-byte number works, like for its encoding.
+        assert_eq!(stack.topnum_sized(-1, 8, true), Ok(i64::MAX));
+        assert_eq!(
+            read_scriptint(&stack.topstr(-1).unwrap(), 8, true),
+            Ok(i64::MAX)
+        );
+        assert_eq!(stack.popnum64(true), Ok(i64::MAX));
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn into_witness_matches_iter_str() {
+        let mut stack: Stack = [0, -1, 1 << 40].into_iter().collect();
+        stack.pushstr(b"abc");
+        let expected: Vec<Vec<u8>> = stack.iter_str().collect();
+        assert_eq!(
+            expected,
+            [vec![], vec![0x81], scriptint_vec(1 << 40), b"abc".to_vec()]
+        );
+        assert_eq!(stack.snapshot().into_witness(), expected);
+        assert_eq!(stack.into_witness(), expected);
+    }
+
+    #[test]
+    fn try_popnum_returns_invalid_bytes() {
+        let five_bytes = [0, 0, 0, 0, 1];
+        let mut stack = Stack::new();
+        assert_eq!(stack.try_popnum(true), None);
+
+        stack.pushnum(1 << 40);
+        stack.pushstr(&[1, 0]);
+        stack.pushstr(&five_bytes);
+        stack.pushnum(-5);
+        assert_eq!(stack.try_popnum(true), Some(Ok(-5)));
+        assert_eq!(
+            stack.try_popnum(true),
+            Some(Err((five_bytes.to_vec(), ScriptIntError::NumericOverflow)))
+        );
+        assert_eq!(
+            stack.try_popnum(true),
+            Some(Err((vec![1, 0], ScriptIntError::NonMinimalPush)))
+        );
+        assert_eq!(
+            stack.try_popnum(true),
+            Some(Err((
+                scriptint_vec(1 << 40),
+                ScriptIntError::NumericOverflow
+            )))
+        );
+        assert!(stack.is_empty());
+    }
+}
@@ -0,0 +1,253 @@
+//! Differential testing against Bitcoin Core's libbitcoinconsensus.
+//!
+//! This is only meant to be used in tests to check that this crate reaches
+//! the same verdict as consensus for a given input. Only legacy and P2SH
+//! spends are executed on our side, witness programs are not supported.
+
+use bitcoin::hashes::Hash;
+use bitcoin::taproot::TapLeafHash;
+use bitcoin::{consensus, Script, ScriptBuf, Transaction, TxOut};
+
+use crate::{Exec, ExecCtx, Options, TxTemplate};
+
+/// Evaluate P2SH subscripts (BIP 16).
+pub const VERIFY_P2SH: u32 = 1 << 0;
+/// Enforce strict DER signatures (BIP 66).
+pub const VERIFY_DERSIG: u32 = 1 << 2;
+/// Enforce NULLDUMMY (BIP 147).
+pub const VERIFY_NULLDUMMY: u32 = 1 << 4;
+/// Enable CHECKLOCKTIMEVERIFY (BIP 65).
+pub const VERIFY_CHECKLOCKTIMEVERIFY: u32 = 1 << 9;
+/// Enable CHECKSEQUENCEVERIFY (BIP 112).
+pub const VERIFY_CHECKSEQUENCEVERIFY: u32 = 1 << 10;
+
+/// The verdicts of both implementations for a single input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Verdicts {
+    /// Whether this crate accepted the spend.
+    pub scriptexec: bool,
+    /// Whether libbitcoinconsensus accepted the spend.
+    pub consensus: bool,
+}
+
+impl Verdicts {
+    pub fn agree(&self) -> bool {
+        self.scriptexec == self.consensus
+    }
+}
+
+/// Verifies input [input_idx] of [tx] with both this crate and libbitcoinconsensus.
+///
+/// The [flags] are libbitcoinconsensus verification flags.
+pub fn compare_with_consensus(
+    tx: &Transaction,
+    input_idx: usize,
+    prevouts: &[TxOut],
+    flags: u32,
+) -> Verdicts {
+    let prevout = &prevouts[input_idx];
+    let consensus = consensus::verify_script_with_flags(
+        &prevout.script_pubkey,
+        input_idx,
+        prevout.value,
+        &consensus::serialize(tx),
+        flags,
+    )
+    .is_ok();
+
+    Verdicts {
+        scriptexec: verify_legacy(tx, input_idx, prevouts, flags),
+        consensus,
+    }
+}
+
+/// Panics if this crate and libbitcoinconsensus disagree on the given input.
+pub fn assert_matches_consensus(
+    tx: &Transaction,
+    input_idx: usize,
+    prevouts: &[TxOut],
+    flags: u32,
+) {
+    let verdicts = compare_with_consensus(tx, input_idx, prevouts, flags);
+    assert!(
+        verdicts.agree(),
+        "verdict mismatch for input {}: scriptexec={}, consensus={}",
+        input_idx,
+        verdicts.scriptexec,
+        verdicts.consensus,
+    );
+}
+
+fn verify_legacy(tx: &Transaction, input_idx: usize, prevouts: &[TxOut], flags: u32) -> bool {
+    let script_sig = &tx.input[input_idx].script_sig;
+    let script_pubkey = &prevouts[input_idx].script_pubkey;
+
+    let stack = match run(tx, input_idx, prevouts, flags, script_sig, vec![]) {
+        Some((stack, _)) => stack,
+        None => return false,
+    };
+    match run(tx, input_idx, prevouts, flags, script_pubkey, stack.clone()) {
+        Some((_, true)) => {}
+        _ => return false,
+    }
+
+    if flags & VERIFY_P2SH != 0 && script_pubkey.is_p2sh() {
+        if !script_sig.is_push_only() {
+            return false;
+        }
+        let mut stack = stack;
+        let redeem_script = match stack.pop() {
+            Some(s) => ScriptBuf::from_bytes(s),
+            None => return false,
+        };
+        return matches!(
+            run(tx, input_idx, prevouts, flags, &redeem_script, stack),
+            Some((_, true))
+        );
+    }
+
+    true
+}
+
+/// Runs a single script and returns the final stack and whether it succeeded,
+/// or [None] if execution failed with an error.
+fn run(
+    tx: &Transaction,
+    input_idx: usize,
+    prevouts: &[TxOut],
+    flags: u32,
+    script: &Script,
+    stack: Vec<Vec<u8>>,
+) -> Option<(Vec<Vec<u8>>, bool)> {
+    let opt = Options {
+        require_minimal: false,
+        strict_der: flags & VERIFY_DERSIG != 0,
+        // libbitcoinconsensus doesn't expose the NULLFAIL policy flag.
+        null_fail: false,
+        verify_cltv: flags & VERIFY_CHECKLOCKTIMEVERIFY != 0,
+        verify_csv: flags & VERIFY_CHECKSEQUENCEVERIFY != 0,
+        ..Default::default()
+    };
+    let tx = TxTemplate {
+        tx: tx.clone(),
+        prevouts: prevouts.to_vec(),
+        input_idx,
+        taproot_annex_scriptleaf: Some((TapLeafHash::all_zeros(), None)),
+    };
+    let mut exec = Exec::new(ExecCtx::Legacy, opt, tx, script.to_owned(), stack).ok()?;
+    while exec.exec_next().is_ok() {}
+
+    let res = exec.result().unwrap();
+    if res.error.is_some() {
+        return None;
+    }
+    Some((res.final_stack.iter_str().collect(), res.success))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::hash160;
+    use bitcoin::opcodes::all::*;
+    use bitcoin::script::{Builder, PushBytesBuf};
+    use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+    use bitcoin::sighash::SighashCache;
+    use bitcoin::{absolute, transaction, Amount, OutPoint, Sequence, TxIn, Witness};
+
+    const FLAGS: u32 = VERIFY_P2SH | VERIFY_DERSIG | VERIFY_NULLDUMMY;
+
+    /// A transaction spending a single output with [script_pubkey].
+    fn spend(script_pubkey: ScriptBuf) -> (Transaction, Vec<TxOut>) {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut::NULL],
+        };
+        let prevout = TxOut {
+            value: Amount::from_sat(1000),
+            script_pubkey,
+        };
+        (tx, vec![prevout])
+    }
+
+    /// A P2PKH spend, with a signature that does or doesn't commit to the transaction.
+    fn p2pkh(valid: bool) -> (Transaction, Vec<TxOut>) {
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = sk.public_key(&secp).serialize();
+        let script_pubkey = Builder::new()
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_HASH160)
+            .push_slice(hash160::Hash::hash(&pk).to_byte_array())
+            .push_opcode(OP_EQUALVERIFY)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let (mut tx, prevouts) = spend(script_pubkey.clone());
+
+        let sighash = SighashCache::new(&tx)
+            .legacy_signature_hash(0, &script_pubkey, 1)
+            .unwrap();
+        let mut digest = sighash.to_byte_array();
+        if !valid {
+            digest[0] ^= 1;
+        }
+        let msg = Message::from_digest(digest);
+        let mut sig = secp.sign_ecdsa(&msg, &sk).serialize_der().to_vec();
+        sig.push(0x01);
+        tx.input[0].script_sig = Builder::new()
+            .push_slice(PushBytesBuf::try_from(sig).unwrap())
+            .push_slice(pk)
+            .into_script();
+        (tx, prevouts)
+    }
+
+    #[test]
+    fn p2pkh_verdicts_match() {
+        let (tx, prevouts) = p2pkh(true);
+        let verdicts = compare_with_consensus(&tx, 0, &prevouts, FLAGS);
+        assert_eq!(
+            verdicts,
+            Verdicts {
+                scriptexec: true,
+                consensus: true
+            }
+        );
+
+        let (tx, prevouts) = p2pkh(false);
+        let verdicts = compare_with_consensus(&tx, 0, &prevouts, FLAGS);
+        assert_eq!(
+            verdicts,
+            Verdicts {
+                scriptexec: false,
+                consensus: false
+            }
+        );
+    }
+
+    #[test]
+    fn p2sh_verdicts_match() {
+        let redeem_script = Builder::new()
+            .push_int(2)
+            .push_opcode(OP_EQUAL)
+            .into_script();
+        for (n, accepted) in [(2, true), (3, false)] {
+            let (mut tx, prevouts) = spend(redeem_script.to_p2sh());
+            tx.input[0].script_sig = Builder::new()
+                .push_int(n)
+                .push_slice(PushBytesBuf::try_from(redeem_script.to_bytes()).unwrap())
+                .into_script();
+            let verdicts = compare_with_consensus(&tx, 0, &prevouts, FLAGS);
+            assert_eq!(verdicts.consensus, accepted);
+            assert_matches_consensus(&tx, 0, &prevouts, FLAGS);
+
+            // Without P2SH, only the hash of the redeem script is checked.
+            assert_matches_consensus(&tx, 0, &prevouts, FLAGS & !VERIFY_P2SH);
+        }
+    }
+}
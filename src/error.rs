@@ -25,15 +25,25 @@ pub enum ExecError {
     PubkeyType,
     SchnorrSigSize,
     SchnorrSigHashtype,
+    SigHashType,
+    SigDer,
+    NullFail,
     SchnorrSig,
     TapscriptCheckMultiSig,
     PubkeyCount,
+    SigCount,
+    CheckMultiSigVerify,
+    ScriptSize,
     StackSize,
     WitnessPubkeyType,
 
     // new ones for us
     ScriptIntNumericOverflow,
     Debug,
+    ResourceExhausted,
+    InvalidSighash,
+    /// Signature checks are not available without the `verify` feature.
+    CryptoUnavailable,
 
     DivByZero,
 }
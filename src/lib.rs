@@ -2,21 +2,23 @@ extern crate alloc;
 extern crate core;
 
 use alloc::borrow::Cow;
+use alloc::rc::Rc;
+use core::cell::RefCell;
 use core::cmp;
+use std::collections::{vec_deque, HashMap, VecDeque};
 
 use bitcoin::consensus::Encodable;
-use bitcoin::hashes::{hash160, ripemd160, sha1, sha256, sha256d, Hash};
+use bitcoin::hashes::{hash160, ripemd160, sha1, sha256, sha256d, Hash, HashEngine};
 use bitcoin::hex::DisplayHex;
 use bitcoin::opcodes::{all::*, Opcode};
 use bitcoin::script::{self, Instruction, Instructions, Script, ScriptBuf};
+use bitcoin::secp256k1;
+#[cfg(feature = "verify")]
 use bitcoin::sighash::SighashCache;
 use bitcoin::taproot::{self, TapLeafHash};
 use bitcoin::transaction::{self, Transaction, TxOut};
 use bitcoin::Sequence;
 
-#[cfg(feature = "serde")]
-use serde;
-
 #[macro_use]
 mod macros;
 
@@ -24,14 +26,32 @@ pub mod utils;
 use utils::ConditionStack;
 
 mod signatures;
+#[cfg(feature = "verify")]
+pub use signatures::{parse_ecdsa_sig, parse_schnorr_sig, verify_taproot_commitment, ParsedSig};
+pub use signatures::{parse_ecdsa_sighash_type, parse_taproot_sighash_type};
 
 mod error;
 pub use error::{Error, ExecError};
 
+mod analysis;
+pub use analysis::{
+    analyze_errors, is_provably_false, is_standard, min_stack_depth, ScriptIssue,
+    StandardnessResult, UnderflowAt,
+};
+
+mod opcode_info;
+pub use opcode_info::{supported_opcodes, OpcodeCategory, OpcodeInfo};
+
+mod taint;
+pub use taint::Taint;
+use taint::{TaintPre, TaintState};
+
+#[cfg(feature = "bitcoinconsensus")]
+pub mod differential;
+
 mod data_structures;
-use crate::data_structures::ScriptIntError;
 use crate::utils::read_scriptint_size;
-pub use data_structures::Stack;
+pub use data_structures::{ScriptIntError, Stack, StackEntry};
 
 /// Maximum number of non-push operations per script
 const MAX_OPS_PER_SCRIPT: usize = 201;
@@ -39,6 +59,9 @@ const MAX_OPS_PER_SCRIPT: usize = 201;
 /// Maximum number of bytes pushable to the stack
 const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
 
+/// Maximum script length in bytes (legacy and segwit v0 only)
+const MAX_SCRIPT_SIZE: usize = 10_000;
+
 /// Maximum number of values on script interpreter stack
 const MAX_STACK_SIZE: usize = 1000;
 
@@ -56,7 +79,7 @@ const VALIDATION_WEIGHT_OFFSET: i64 = 50;
 const VALIDATION_WEIGHT_PER_SIGOP_PASSED: i64 = 50;
 
 // Maximum number of public keys per multisig
-const _MAX_PUBKEYS_PER_MULTISIG: i64 = 20;
+const MAX_PUBKEYS_PER_MULTISIG: i64 = 20;
 
 /// Used to enable experimental script features.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -71,20 +94,162 @@ pub struct Experimental {
     pub op_div: bool,
 }
 
+/// How to treat an executed OP_RETURN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OpReturnPolicy {
+    /// Fail the script, as required by consensus.
+    #[default]
+    Fail,
+    /// Stop executing and succeed, leaving the remainder of the script
+    /// available through [Exec::op_return_data].
+    StopSuccess,
+    /// Treat OP_RETURN as a NOP and keep executing.
+    Ignore,
+}
+
+/// The cost charged for every executed instruction, see [Exec::total_cost].
+///
+/// Pushes are charged by their opcode, like OP_PUSHBYTES_32 or OP_PUSHDATA1.
+/// Opcodes without a specific cost are charged the default cost, which is 1
+/// unless set otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CostModel {
+    default: u64,
+    costs: HashMap<u8, u64>,
+}
+
+impl CostModel {
+    /// A cost model charging [default] for every opcode.
+    pub fn new(default: u64) -> Self {
+        CostModel {
+            default,
+            costs: HashMap::new(),
+        }
+    }
+
+    /// Sets the cost of [op].
+    pub fn with_cost(mut self, op: Opcode, cost: u64) -> Self {
+        self.costs.insert(op.to_u8(), cost);
+        self
+    }
+
+    pub fn cost(&self, op: Opcode) -> u64 {
+        self.costs.get(&op.to_u8()).copied().unwrap_or(self.default)
+    }
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        CostModel::new(1)
+    }
+}
+
+/// Resource limits on top of the consensus limits.
+///
+/// These are not consensus rules, but allow bounding the work done for
+/// adversarial scripts. All limits are disabled by default.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ExecLimits {
+    /// The maximum total number of positions elements can be moved by OP_ROLL.
+    ///
+    /// Each OP_ROLL is linear in the depth of the rolled element, so a loop
+    /// of deep rolls is quadratic in the stack size.
+    pub max_roll_displacement: Option<usize>,
+    /// The maximum total size in bytes of all elements on the stack and altstack.
+    ///
+    /// Opcodes like OP_CAT or OP_2DUP allow a short script to grow the stack
+    /// exponentially, this bounds the memory used by such scripts.
+    pub max_total_bytes: Option<usize>,
+    /// The maximum total number of bytes hashed by the hash opcodes.
+    ///
+    /// Signature checks are not included, they are bounded by the sigops limits.
+    pub max_hash_bytes: Option<usize>,
+    /// The maximum script length in bytes, checked when creating an [Exec].
+    ///
+    /// Legacy and segwit v0 scripts are always limited to 10,000 bytes by
+    /// consensus, this additionally allows limiting Tapscript scripts.
+    pub max_script_size: Option<usize>,
+}
+
 /// Used to fine-tune different variables during execution.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Options {
-    /// Require data pushes be minimally encoded.
+    /// Require numeric operands be minimally encoded.
     pub require_minimal: bool, //TODO(stevenroose) double check all fRequireMinimal usage in Core
+    /// With [Options::require_minimal], require minimal operands for the arithmetic
+    /// and boolean opcodes, from OP_1ADD to OP_BOOLOR.
+    pub minimal_arithmetic: bool,
+    /// With [Options::require_minimal], require minimal operands for the numeric
+    /// comparisons, from OP_NUMEQUAL to OP_WITHIN.
+    pub minimal_comparison: bool,
+    /// With [Options::require_minimal], require minimal operands for OP_CLTV and OP_CSV.
+    pub minimal_locktime: bool,
+    /// With [Options::require_minimal], require executed data pushes use the
+    /// smallest possible push opcode.
+    pub minimal_push: bool,
+    /// The maximum size in bytes of numeric operands.
+    ///
+    /// This is 4 by consensus, wider operands up to 8 bytes can be allowed for
+    /// research purposes, but anything above 4 is non-consensus.
+    pub max_num_size: usize,
     /// Verify OP_CHECKLOCKTIMEVERIFY.
     pub verify_cltv: bool,
     /// Verify OP_CHECKSEQUENCEVERIFY.
     pub verify_csv: bool,
     /// Verify conditionals are minimally encoded.
     pub verify_minimal_if: bool,
+    /// Require ECDSA signatures be strict DER encoded (BIP 66).
+    ///
+    /// BIP 66 is a consensus rule, so this is enabled by default. Disable it
+    /// to execute scripts from before its activation.
+    pub strict_der: bool,
+    /// Require ECDSA signatures use a defined sighash type, like Bitcoin Core's
+    /// SCRIPT_VERIFY_STRICTENC policy, see [parse_ecdsa_sighash_type].
+    ///
+    /// Consensus accepts any sighash byte, so this is disabled by default.
+    pub strict_sighash: bool,
+    /// Require ECDSA public keys be compressed or uncompressed SEC encoded,
+    /// like Bitcoin Core's SCRIPT_VERIFY_STRICTENC policy.
+    ///
+    /// Consensus lets keys that don't parse fail the check instead, so this is
+    /// disabled by default.
+    pub strict_pubkey: bool,
+    /// Require failing ECDSA signatures be empty (BIP 146 NULLFAIL).
+    ///
+    /// This is a standardness policy for legacy and segwit v0 scripts, so it
+    /// is disabled by default. In Tapscript this is always enforced, as
+    /// required by consensus.
+    pub null_fail: bool,
 	/// Enfore a strict limit of 1000 total stack items.
 	pub enforce_stack_limit: bool,
-
+    /// Keep a snapshot of the stack after every step, see [Exec::history].
+    pub keep_history: bool,
+    /// The maximum number of stack snapshots to keep, older ones are dropped first.
+    pub max_history: Option<usize>,
+    /// Defer verification of Schnorr signatures until the end of the script.
+    ///
+    /// In Tapscript a non-empty signature either passes or fails the whole
+    /// script, so checks can be assumed to succeed while executing. The only
+    /// observable difference is that when a signature is invalid, execution
+    /// runs to the end and fails with [ExecError::SchnorrSig] there, unless
+    /// another error occurs first.
+    pub defer_schnorr_verification: bool,
+    /// Record the outcome of every signature check, see [Exec::sig_checks].
+    pub record_sig_checks: bool,
+    /// How to treat an executed OP_RETURN, anything but the default is non-consensus.
+    pub op_return_policy: OpReturnPolicy,
+    /// Track which witness elements every value depends on, see [Exec::stack_taint].
+    pub track_taint: bool,
+    /// The serialized size of the input's full witness, which sets the initial
+    /// Tapscript validation weight budget of BIP 342 to 50 plus this size.
+    ///
+    /// By default, the size of the script witness given to [Exec::new] is used,
+    /// which doesn't include the script and the control block.
+    pub witness_size: Option<usize>,
+    /// The costs summed in [Exec::total_cost].
+    pub cost_model: CostModel,
+
+    pub limits: ExecLimits,
     pub experimental: Experimental,
 }
 
@@ -92,10 +257,28 @@ impl Default for Options {
     fn default() -> Self {
         Options {
             require_minimal: true,
+            minimal_arithmetic: true,
+            minimal_comparison: true,
+            minimal_locktime: true,
+            minimal_push: true,
+            max_num_size: 4,
             verify_cltv: true,
             verify_csv: true,
             verify_minimal_if: true,
+            strict_der: true,
+            strict_sighash: false,
+            strict_pubkey: false,
+            null_fail: false,
             enforce_stack_limit: true,
+            keep_history: false,
+            max_history: None,
+            defer_schnorr_verification: false,
+            record_sig_checks: false,
+            op_return_policy: OpReturnPolicy::Fail,
+            track_taint: false,
+            witness_size: None,
+            cost_model: CostModel::default(),
+            limits: ExecLimits::default(),
             experimental: Experimental {
                 op_cat: true,
                 op_mul: false,
@@ -109,10 +292,28 @@ impl Options {
     pub fn default_with_mul_div() -> Self {
         Options {
             require_minimal: true,
+            minimal_arithmetic: true,
+            minimal_comparison: true,
+            minimal_locktime: true,
+            minimal_push: true,
+            max_num_size: 4,
             verify_cltv: true,
             verify_csv: true,
             verify_minimal_if: true,
+            strict_der: true,
+            strict_sighash: false,
+            strict_pubkey: false,
+            null_fail: false,
             enforce_stack_limit: true,
+            keep_history: false,
+            max_history: None,
+            defer_schnorr_verification: false,
+            record_sig_checks: false,
+            op_return_policy: OpReturnPolicy::Fail,
+            track_taint: false,
+            witness_size: None,
+            cost_model: CostModel::default(),
+            limits: ExecLimits::default(),
             experimental: Experimental {
                 op_cat: true,
                 op_mul: true,
@@ -129,13 +330,66 @@ pub enum ExecCtx {
     Tapscript,
 }
 
+#[derive(Debug, Clone)]
 pub struct TxTemplate {
     pub tx: Transaction,
+    /// The outputs spent by the transaction inputs, indexed like the inputs.
+    ///
+    /// Tapscript requires all of them, segwit v0 only the one spent by
+    /// the input being validated.
     pub prevouts: Vec<TxOut>,
     pub input_idx: usize,
     pub taproot_annex_scriptleaf: Option<(TapLeafHash, Option<Vec<u8>>)>,
 }
 
+impl TxTemplate {
+    /// A version 2 transaction with a single input spending an empty output,
+    /// which is enough to execute scripts in any context.
+    pub fn dummy() -> Self {
+        Self::with_timelocks(
+            transaction::Version::TWO,
+            bitcoin::locktime::absolute::LockTime::ZERO,
+            Sequence::MAX,
+        )
+    }
+
+    /// Like [TxTemplate::dummy], with the fields checked by OP_CLTV and OP_CSV
+    /// set to the given values.
+    pub fn with_timelocks(
+        version: transaction::Version,
+        lock_time: bitcoin::locktime::absolute::LockTime,
+        sequence: Sequence,
+    ) -> Self {
+        TxTemplate {
+            tx: Transaction {
+                version,
+                lock_time,
+                input: vec![transaction::TxIn {
+                    sequence,
+                    ..Default::default()
+                }],
+                output: vec![],
+            },
+            prevouts: vec![TxOut::NULL],
+            input_idx: 0,
+            taproot_annex_scriptleaf: Some((TapLeafHash::all_zeros(), None)),
+        }
+    }
+}
+
+/// Whether a final stack is accepted, and if so, whether it is clean.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Acceptance {
+    /// The stack holds a single true element.
+    AcceptClean,
+    /// The top element is true, but there are other elements left.
+    ///
+    /// This is only accepted in legacy scripts without the clean stack rule.
+    AcceptDirty,
+    /// Execution failed, the stack is empty or the top element is false.
+    Reject,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ExecutionResult {
     pub success: bool,
@@ -146,32 +400,39 @@ pub struct ExecutionResult {
 
 impl ExecutionResult {
     fn from_final_stack(ctx: ExecCtx, final_stack: Stack) -> ExecutionResult {
+        let acceptance = Self::stack_acceptance(&final_stack);
         ExecutionResult {
             success: match ctx {
-                ExecCtx::Legacy => {
-                    if final_stack.is_empty() {
-                        false
-                    } else if !script::read_scriptbool(&final_stack.last().unwrap()) {
-                        false
-                    } else {
-                        true
-                    }
-                }
-                ExecCtx::SegwitV0 | ExecCtx::Tapscript => {
-                    if final_stack.len() != 1 {
-                        false
-                    } else if !script::read_scriptbool(&final_stack.last().unwrap()) {
-                        false
-                    } else {
-                        true
-                    }
-                }
+                ExecCtx::Legacy => acceptance != Acceptance::Reject,
+                ExecCtx::SegwitV0 | ExecCtx::Tapscript => acceptance == Acceptance::AcceptClean,
             },
-            final_stack: final_stack,
+            final_stack,
             error: None,
             opcode: None,
         }
     }
+
+    fn stack_acceptance(stack: &Stack) -> Acceptance {
+        match stack.last() {
+            Ok(top) if script::read_scriptbool(&top) => {
+                if stack.len() == 1 {
+                    Acceptance::AcceptClean
+                } else {
+                    Acceptance::AcceptDirty
+                }
+            }
+            _ => Acceptance::Reject,
+        }
+    }
+
+    /// Classifies the final stack independently of the clean stack rule of
+    /// the context, so that callers can apply their own policy.
+    pub fn acceptance(&self) -> Acceptance {
+        if self.error.is_some() {
+            return Acceptance::Reject;
+        }
+        Self::stack_acceptance(&self.final_stack)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
@@ -185,19 +446,98 @@ pub struct ExecStats {
     /// per signature in CHECKMULTISIG.
     pub opcode_count: usize,
 
+    /// The number of signature checks performed.
+    pub sigops: usize,
+    /// The total number of bytes hashed by the hash opcodes.
+    pub hashed_bytes: usize,
+
     /// The validation weight execution started with.
     pub start_validation_weight: i64,
     /// The current remaining validation weight.
     pub validation_weight: i64,
 }
 
+/// The outcome of a single signature check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigCheck {
+    /// The script position of the opcode performing the check.
+    pub position: usize,
+    pub opcode: Opcode,
+    /// For CHECKMULTISIG, the index of the public key in script order.
+    pub key_index: usize,
+    /// For CHECKMULTISIG, the index of the signature in script order.
+    pub sig_index: usize,
+    pub pubkey: Vec<u8>,
+    pub success: bool,
+}
+
+/// An owned snapshot of the full machine state, see [Exec::state].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MachineState {
+    pub stack: Vec<Vec<u8>>,
+    pub altstack: Vec<Vec<u8>>,
+    /// The number of nested conditionals.
+    pub cond_stack_depth: usize,
+    /// Whether all nested conditionals are in their executed branch.
+    pub executing: bool,
+    pub script_position: usize,
+    pub opcode_count: usize,
+    pub last_codeseparator_pos: Option<u32>,
+}
+
+/// A saved execution state of an [Exec], see [Exec::checkpoint].
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    result: Option<ExecutionResult>,
+    script_position: usize,
+    cond_stack: ConditionStack,
+    stack: Stack,
+    altstack: Stack,
+    last_codeseparator_pos: Option<u32>,
+    opcode_count: usize,
+    validation_weight: i64,
+    roll_displacement: usize,
+    sigops: usize,
+    hashed_bytes: usize,
+    total_cost: u64,
+    stats: ExecStats,
+    history: VecDeque<Stack>,
+    op_return_pos: Option<usize>,
+    sig_checks: Vec<SigCheck>,
+    deferred_schnorr: Vec<(
+        secp256k1::schnorr::Signature,
+        secp256k1::Message,
+        secp256k1::XOnlyPublicKey,
+    )>,
+    taint: Option<TaintState>,
+}
+
+/// The parts of the execution state exposed to custom opcode handlers.
+pub struct ExecState<'a> {
+    pub ctx: ExecCtx,
+    pub stack: &'a mut Stack,
+    pub altstack: &'a mut Stack,
+}
+
+/// A handler for an opcode registered with [Exec::register_opcode].
+pub type OpcodeHandler = Box<dyn Fn(&mut ExecState) -> Result<(), ExecError>>;
+
 /// Partial execution of a script.
+///
+/// An [Exec] is not [Send], as its stacks share byte strings through [Rc]s.
+/// To execute a script on several threads, share the [ScriptBuf] and create
+/// an [Exec] on each thread: executions share no mutable state, so they can
+/// run in parallel.
+///
+/// [Rc]: alloc::rc::Rc
 pub struct Exec {
     ctx: ExecCtx,
     opt: Options,
     tx: TxTemplate,
     result: Option<ExecutionResult>,
 
+    #[cfg(feature = "verify")]
     sighashcache: SighashCache<Transaction>,
     script: &'static Script,
     instructions: Instructions<'static>,
@@ -212,9 +552,29 @@ pub struct Exec {
 
     opcode_count: usize,
     validation_weight: i64,
+    roll_displacement: usize,
+    sigops: usize,
+    hashed_bytes: usize,
+    total_cost: u64,
 
     // runtime statistics
     stats: ExecStats,
+    // stack snapshots, only kept if enabled in the options
+    history: VecDeque<Stack>,
+    // the script position after an OP_RETURN that stopped execution
+    op_return_pos: Option<usize>,
+    // handlers for custom opcodes, indexed by opcode byte
+    custom_opcodes: HashMap<u8, OpcodeHandler>,
+    // signature check outcomes, only kept if enabled in the options
+    sig_checks: Vec<SigCheck>,
+    // Schnorr signatures left to verify at the end, if deferred
+    deferred_schnorr: Vec<(
+        secp256k1::schnorr::Signature,
+        secp256k1::Message,
+        secp256k1::XOnlyPublicKey,
+    )>,
+    // the dependencies of the stack values on the witness, if tracked
+    taint: Option<TaintState>,
 }
 
 impl std::ops::Drop for Exec {
@@ -241,20 +601,42 @@ impl Exec {
             }
 
             if let Some((_, Some(ref annex))) = tx.taproot_annex_scriptleaf {
-                if annex.get(0) != Some(&taproot::TAPROOT_ANNEX_PREFIX) {
+                if annex.first() != Some(&taproot::TAPROOT_ANNEX_PREFIX) {
                     return Err(Error::Other("invalid annex: missing prefix"));
                 }
             }
+
+            // The taproot sighash commits to all spent outputs.
+            if tx.prevouts.len() != tx.tx.input.len() {
+                return Err(Error::Other(
+                    "number of prevouts doesn't match number of inputs",
+                ));
+            }
+        }
+        if ctx == ExecCtx::SegwitV0 && tx.input_idx >= tx.prevouts.len() {
+            // The segwit v0 sighash commits to the spent amount.
+            return Err(Error::Other("missing prevout for spent input"));
+        }
+
+        if opt.max_num_size == 0 || opt.max_num_size > 8 {
+            return Err(Error::Other(
+                "numeric operand size must be between 1 and 8 bytes",
+            ));
+        }
+
+        if ctx != ExecCtx::Tapscript && script.len() > MAX_SCRIPT_SIZE {
+            return Err(Error::Exec(ExecError::ScriptSize));
+        }
+        if let Some(max) = opt.limits.max_script_size {
+            if script.len() > max {
+                return Err(Error::Exec(ExecError::ScriptSize));
+            }
         }
 
         // We want to make sure the script is valid so we don't have to throw parsing errors
-        // while executing.
-        let instructions = if opt.require_minimal {
-            script.instructions_minimal()
-        } else {
-            script.instructions()
-        };
-        if let Some(err) = instructions.clone().find_map(|res| res.err()) {
+        // while executing. Push minimality is checked during execution, because
+        // non-minimal pushes in unexecuted branches are allowed.
+        if let Some(err) = script.instructions().find_map(|res| res.err()) {
             return Err(Error::InvalidScript(err));
         }
 
@@ -266,47 +648,121 @@ impl Exec {
         // We box alocate the script to get a static Instructions iterator.
         // We will manually drop this allocation in the ops::Drop impl.
         let script = Box::leak(script.into_boxed_script()) as &'static Script;
-        let instructions = if opt.require_minimal {
-            script.instructions_minimal()
-        } else {
-            script.instructions()
-        };
-
-        //TODO(stevenroose) make this more efficient
-        let witness_size =
-            Encodable::consensus_encode(&script_witness, &mut bitcoin::io::sink()).unwrap();
-        let start_validation_weight = VALIDATION_WEIGHT_OFFSET + witness_size as i64;
+        let instructions = script.instructions();
 
         let mut ret = Exec {
-            ctx: ctx,
+            ctx,
             result: None,
 
+            #[cfg(feature = "verify")]
             sighashcache: SighashCache::new(tx.tx.clone()),
-            script: script,
-            instructions: instructions,
+            script,
+            instructions,
             current_position: 0,
             cond_stack: ConditionStack::new(),
-            //TODO(stevenroose) does this need to be reversed?
-            stack: Stack::from_u8_vec(script_witness),
+            stack: Stack::new(),
             altstack: Stack::new(),
             opcode_count: 0,
-            validation_weight: start_validation_weight,
+            validation_weight: 0,
+            roll_displacement: 0,
+            sigops: 0,
+            hashed_bytes: 0,
+            total_cost: 0,
             last_codeseparator_pos: None,
             script_code: script,
 
-            opt: opt,
-            tx: tx,
+            opt,
+            tx,
 
-            stats: ExecStats {
-                start_validation_weight: start_validation_weight,
-                validation_weight: start_validation_weight,
-                ..Default::default()
-            },
+            stats: ExecStats::default(),
+            history: VecDeque::new(),
+            op_return_pos: None,
+            custom_opcodes: HashMap::new(),
+            sig_checks: Vec::new(),
+            deferred_schnorr: Vec::new(),
+            taint: None,
         };
-        ret.update_stats();
+        ret.reset(script_witness);
         Ok(ret)
     }
 
+    /// Resets the execution to the start of the script with [script_witness]
+    /// as the initial stack, keeping the allocations of the previous run.
+    pub(crate) fn reset(&mut self, script_witness: Vec<Vec<u8>>) {
+        //TODO(stevenroose) make this more efficient
+        let witness_size = self.opt.witness_size.unwrap_or_else(|| {
+            Encodable::consensus_encode(&script_witness, &mut bitcoin::io::sink()).unwrap()
+        });
+        let start_validation_weight = VALIDATION_WEIGHT_OFFSET + witness_size as i64;
+
+        self.result = None;
+        self.instructions = self.script.instructions();
+        self.current_position = 0;
+        self.cond_stack = ConditionStack::new();
+        self.taint = self
+            .opt
+            .track_taint
+            .then(|| TaintState::new(script_witness.len()));
+        //TODO(stevenroose) does this need to be reversed?
+        self.stack.clear();
+        for entry in script_witness {
+            self.stack
+                .push(StackEntry::StrRef(Rc::new(RefCell::new(entry))));
+        }
+        self.altstack.clear();
+        self.opcode_count = 0;
+        self.validation_weight = start_validation_weight;
+        self.roll_displacement = 0;
+        self.sigops = 0;
+        self.hashed_bytes = 0;
+        self.total_cost = 0;
+        self.last_codeseparator_pos = None;
+        self.script_code = self.script;
+        self.stats = ExecStats {
+            start_validation_weight,
+            validation_weight: start_validation_weight,
+            ..Default::default()
+        };
+        self.history.clear();
+        self.op_return_pos = None;
+        self.sig_checks.clear();
+        self.deferred_schnorr.clear();
+        self.update_stats();
+        self.record_history();
+    }
+
+    /// Creates an [Exec] running [instructions] instead of a serialized script.
+    ///
+    /// The instructions are encoded into the script returned by [Exec::script],
+    /// using the shortest length prefix for pushes. Script positions, the scriptCode
+    /// committed to by signatures and OP_CODESEPARATOR all refer to that
+    /// encoding, which may differ from the one the instructions were parsed from.
+    pub fn from_instructions<'a>(
+        ctx: ExecCtx,
+        opt: Options,
+        tx: TxTemplate,
+        instructions: impl IntoIterator<Item = Instruction<'a>>,
+        script_witness: Vec<Vec<u8>>,
+    ) -> Result<Exec, Error> {
+        let mut script = ScriptBuf::new();
+        for ins in instructions {
+            script.push_instruction(ins);
+        }
+        Exec::new(ctx, opt, tx, script, script_witness)
+    }
+
+    /// Registers a handler to be called when [op] is executed.
+    ///
+    /// The handler replaces the default behavior of the opcode, including
+    /// failing for disabled or unknown opcodes. It is only called in executed
+    /// branches and the opcode still counts towards the opcode limit.
+    ///
+    /// Overriding consensus opcodes is possible, but discouraged: scripts will
+    /// no longer be executed according to consensus rules.
+    pub fn register_opcode(&mut self, op: Opcode, handler: OpcodeHandler) {
+        self.custom_opcodes.insert(op.to_u8(), handler);
+    }
+
     //////////////////
     // SOME GETTERS //
     //////////////////
@@ -315,11 +771,36 @@ impl Exec {
         self.result.as_ref()
     }
 
+    /// The script being executed.
+    pub fn script(&self) -> &Script {
+        self.script
+    }
+
     pub fn script_position(&self) -> usize {
         self.script.len() - self.instructions.as_script().len()
     }
 
-    pub fn remaining_script<'a>(&'a self) -> &'a Script {
+    /// Returns the number of script bytes consumed so far and the total script length.
+    pub fn progress(&self) -> (usize, usize) {
+        (self.script_position(), self.script.len())
+    }
+
+    /// The instruction that will be executed by the next call to [Exec::exec_next].
+    pub fn current_instruction(&self) -> Option<Instruction<'_>> {
+        if self.result.is_some() {
+            return None;
+        }
+        self.instructions.clone().next().and_then(|i| i.ok())
+    }
+
+    /// The part of the script following the OP_RETURN that stopped execution.
+    ///
+    /// Only set when using [OpReturnPolicy::StopSuccess].
+    pub fn op_return_data(&self) -> Option<&Script> {
+        self.op_return_pos.map(|pos| &self.script[pos..])
+    }
+
+    pub fn remaining_script(&self) -> &Script {
         let pos = self.script_position();
         &self.script[pos..]
     }
@@ -332,10 +813,176 @@ impl Exec {
         &self.altstack
     }
 
+    /// The number of opcodes counted so far towards the limit of 201 per script.
+    ///
+    /// Pushes are not counted and, like the limit, counting only happens
+    /// outside of Tapscript. CHECKMULTISIG counts once per public key.
+    pub fn op_count(&self) -> usize {
+        self.opcode_count
+    }
+
+    /// The sum of the costs of all instructions executed so far, using the
+    /// costs of [Options::cost_model].
+    ///
+    /// Instructions in unexecuted branches are not charged, except for
+    /// the conditionals.
+    pub fn total_cost(&self) -> u64 {
+        self.total_cost
+    }
+
     pub fn stats(&self) -> &ExecStats {
         &self.stats
     }
 
+    /// A SHA256 digest of the full machine state at the current position.
+    ///
+    /// The stacks, condition stack, script position, counters and result are
+    /// serialized canonically, with byte strings length-prefixed and integers
+    /// as 8-byte little-endian, so the digest is the same on every platform.
+    /// Numbers on the stacks are hashed as their minimal encoding. The script,
+    /// transaction and options are not included, as they don't change during
+    /// execution.
+    pub fn state_hash(&self) -> [u8; 32] {
+        fn write_stack(engine: &mut sha256::HashEngine, stack: &Stack) {
+            engine.input(&(stack.len() as u64).to_le_bytes());
+            for item in stack.iter_str() {
+                engine.input(&(item.len() as u64).to_le_bytes());
+                engine.input(&item);
+            }
+        }
+
+        let mut engine = sha256::Hash::engine();
+        engine.input(&[self.ctx as u8]);
+        engine.input(&(self.script_position() as u64).to_le_bytes());
+        write_stack(&mut engine, &self.stack);
+        write_stack(&mut engine, &self.altstack);
+        engine.input(&(self.cond_stack.depth() as u64).to_le_bytes());
+        let first_false = self.cond_stack.first_false().map_or(u64::MAX, |p| p as u64);
+        engine.input(&first_false.to_le_bytes());
+        let codeseparator = self.last_codeseparator_pos.map_or(u64::MAX, |p| p as u64);
+        engine.input(&codeseparator.to_le_bytes());
+        for n in [
+            self.opcode_count as u64,
+            self.validation_weight as u64,
+            self.roll_displacement as u64,
+            self.sigops as u64,
+            self.hashed_bytes as u64,
+            self.total_cost,
+        ] {
+            engine.input(&n.to_le_bytes());
+        }
+        let result = match self.result {
+            None => 0u8,
+            Some(ref res) if res.success => 1,
+            Some(_) => 2,
+        };
+        engine.input(&[result]);
+        sha256::Hash::from_engine(engine).to_byte_array()
+    }
+
+    /// Captures the full machine state at the current position.
+    pub fn state(&self) -> MachineState {
+        MachineState {
+            stack: self.stack.iter_str().collect(),
+            altstack: self.altstack.iter_str().collect(),
+            cond_stack_depth: self.cond_stack.depth(),
+            executing: self.cond_stack.all_true(),
+            script_position: self.script_position(),
+            opcode_count: self.opcode_count,
+            last_codeseparator_pos: self.last_codeseparator_pos,
+        }
+    }
+
+    /// Saves the current execution state, to be restored with [Exec::restore].
+    ///
+    /// This is cheap: byte strings on the stacks are shared with the checkpoint.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            result: self.result.clone(),
+            script_position: self.script_position(),
+            cond_stack: self.cond_stack.clone(),
+            stack: self.stack.snapshot(),
+            altstack: self.altstack.snapshot(),
+            last_codeseparator_pos: self.last_codeseparator_pos,
+            opcode_count: self.opcode_count,
+            validation_weight: self.validation_weight,
+            roll_displacement: self.roll_displacement,
+            sigops: self.sigops,
+            hashed_bytes: self.hashed_bytes,
+            total_cost: self.total_cost,
+            stats: self.stats.clone(),
+            history: self.history.clone(),
+            op_return_pos: self.op_return_pos,
+            sig_checks: self.sig_checks.clone(),
+            deferred_schnorr: self.deferred_schnorr.clone(),
+            taint: self.taint.clone(),
+        }
+    }
+
+    /// Restores the execution state saved in [cp].
+    ///
+    /// The checkpoint must have been taken from this [Exec], restoring it
+    /// into another one gives unspecified results or panics.
+    pub fn restore(&mut self, cp: Checkpoint) {
+        self.result = cp.result;
+        self.instructions = self.script[cp.script_position..].instructions();
+        self.current_position = cp.script_position;
+        self.cond_stack = cp.cond_stack;
+        self.stack = cp.stack;
+        self.altstack = cp.altstack;
+        self.last_codeseparator_pos = cp.last_codeseparator_pos;
+        self.script_code = match cp.last_codeseparator_pos {
+            Some(pos) => &self.script[pos as usize..],
+            None => self.script,
+        };
+        self.opcode_count = cp.opcode_count;
+        self.validation_weight = cp.validation_weight;
+        self.roll_displacement = cp.roll_displacement;
+        self.sigops = cp.sigops;
+        self.hashed_bytes = cp.hashed_bytes;
+        self.total_cost = cp.total_cost;
+        self.stats = cp.stats;
+        self.history = cp.history;
+        self.op_return_pos = cp.op_return_pos;
+        self.sig_checks = cp.sig_checks;
+        self.deferred_schnorr = cp.deferred_schnorr;
+        self.taint = cp.taint;
+    }
+
+    /// The witness elements each stack entry depends on, from bottom to top.
+    ///
+    /// Only available if [Options::track_taint] is set.
+    pub fn stack_taint(&self) -> Option<&[Taint]> {
+        self.taint.as_ref().map(|t| &t.stack[..])
+    }
+
+    /// The witness elements the outcome of the script depends on, i.e. those the
+    /// top stack entry and the values checked by VERIFY opcodes depend on.
+    ///
+    /// Only available if [Options::track_taint] is set.
+    pub fn result_taint(&self) -> Option<Taint> {
+        let taint = self.taint.as_ref()?;
+        let mut ret = taint.verified.clone();
+        ret.extend(taint.stack.last().into_iter().flatten());
+        Some(ret)
+    }
+
+    /// The outcomes of all signature checks performed so far.
+    ///
+    /// Only available if [Options::record_sig_checks] is set.
+    pub fn sig_checks(&self) -> &[SigCheck] {
+        &self.sig_checks
+    }
+
+    /// The stack before execution followed by a snapshot after every step.
+    ///
+    /// Only available if [Options::keep_history] is set. Snapshots share
+    /// the underlying byte buffers with the live stack. The iterator goes
+    /// from the oldest snapshot to the current stack.
+    pub fn history(&self) -> vec_deque::Iter<'_, Stack> {
+        self.history.iter()
+    }
+
     ///////////////
     // UTILITIES //
     ///////////////
@@ -377,7 +1024,11 @@ impl Exec {
             _ => {}
         }
 
-        if self.tx.tx.input[self.tx.input_idx].sequence.is_final() {
+        let input = match self.tx.tx.input.get(self.tx.input_idx) {
+            Some(i) => i,
+            None => return false,
+        };
+        if input.sequence.is_final() {
             return false;
         }
 
@@ -393,7 +1044,10 @@ impl Exec {
             return false;
         }
 
-        let input_sequence = self.tx.tx.input[self.tx.input_idx].sequence;
+        let input_sequence = match self.tx.tx.input.get(self.tx.input_idx) {
+            Some(i) => i.sequence,
+            None => return false,
+        };
         let input_lock_time = match input_sequence.to_relative_lock_time() {
             Some(lt) => lt,
             None => return false,
@@ -421,24 +1075,54 @@ impl Exec {
         // Drop the signature in pre-segwit scripts but not segwit scripts
         let mut scriptcode = Cow::Borrowed(self.script_code.as_bytes());
         if self.ctx == ExecCtx::Legacy {
-            let mut i = 0;
-            while i < scriptcode.len() - sig.len() {
-                if &scriptcode[i..i + sig.len()] == sig {
-                    scriptcode.to_mut().drain(i..i + sig.len());
-                } else {
-                    i += 1;
-                }
-            }
+            find_and_delete(&mut scriptcode, sig);
         }
 
         //TODO(stevenroose) the signature and pk encoding checks we use here
         // might not be exactly identical to Core's
 
+        self.check_signature_encoding(sig)?;
+        self.check_pubkey_encoding(pk)?;
+
+        // An empty signature is the compact way to fail a check, which is
+        // allowed by NULLFAIL and doesn't need any crypto.
+        if sig.is_empty() {
+            return Ok(false);
+        }
+
+        let ok = self.check_sig_ecdsa(sig, pk, &scriptcode)?;
+        if !ok && self.opt.null_fail {
+            return Err(ExecError::NullFail);
+        }
+        Ok(ok)
+    }
+
+    /// Checks the encoding of an ECDSA signature if required by the options.
+    ///
+    /// An empty signature is always allowed, as a compact way to provide an
+    /// invalid signature.
+    fn check_signature_encoding(&self, sig: &[u8]) -> Result<(), ExecError> {
+        if self.opt.strict_der && !sig.is_empty() && !utils::is_valid_signature_encoding(sig) {
+            return Err(ExecError::SigDer);
+        }
+        if self.opt.strict_sighash && !sig.is_empty() {
+            parse_ecdsa_sighash_type(sig[sig.len() - 1], true)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the encoding of an ECDSA public key.
+    ///
+    /// Uncompressed keys are never allowed in segwit v0 scripts, the format
+    /// is only checked if required by the options.
+    fn check_pubkey_encoding(&self, pk: &[u8]) -> Result<(), ExecError> {
+        if self.opt.strict_pubkey && !utils::is_compressed_or_uncompressed_pubkey(pk) {
+            return Err(ExecError::PubkeyType);
+        }
         if self.ctx == ExecCtx::SegwitV0 && pk.len() == 65 {
             return Err(ExecError::WitnessPubkeyType);
         }
-
-        Ok(self.check_sig_ecdsa(sig, pk, &scriptcode))
+        Ok(())
     }
 
     fn check_sig_tap(&mut self, sig: &[u8], pk: &[u8]) -> Result<bool, ExecError> {
@@ -451,22 +1135,45 @@ impl Exec {
 
         if pk.is_empty() {
             Err(ExecError::PubkeyType)
+        } else if sig.is_empty() {
+            // Like for known key types, an empty signature fails without
+            // any verification.
+            Ok(false)
         } else if pk.len() == 32 {
-            if !sig.is_empty() {
-                self.check_sig_schnorr(sig, pk)?;
-                Ok(true)
-            } else {
-                Ok(false)
-            }
+            self.check_sig_schnorr(sig, pk)?;
+            Ok(true)
         } else {
             Ok(true)
         }
     }
 
-    fn check_sig(&mut self, sig: &[u8], pk: &[u8]) -> Result<bool, ExecError> {
-        match self.ctx {
+    fn check_sig(&mut self, sig: &[u8], pk: &[u8], op: Opcode) -> Result<bool, ExecError> {
+        self.sigops += 1;
+        let res = match self.ctx {
             ExecCtx::Legacy | ExecCtx::SegwitV0 => self.check_sig_pre_tap(sig, pk),
             ExecCtx::Tapscript => self.check_sig_tap(sig, pk),
+        };
+        self.record_sig_check(op, 0, 0, pk, res == Ok(true));
+        res
+    }
+
+    fn record_sig_check(
+        &mut self,
+        op: Opcode,
+        key_index: usize,
+        sig_index: usize,
+        pk: &[u8],
+        success: bool,
+    ) {
+        if self.opt.record_sig_checks {
+            self.sig_checks.push(SigCheck {
+                position: self.current_position,
+                opcode: op,
+                key_index,
+                sig_index,
+                pubkey: pk.to_vec(),
+                success,
+            });
         }
     }
 
@@ -484,6 +1191,9 @@ impl Exec {
         let instruction = match self.instructions.next() {
             Some(Ok(i)) => i,
             None => {
+                if let Err(err) = self.verify_deferred_schnorr() {
+                    return self.fail(err);
+                }
                 let res = ExecutionResult::from_final_stack(self.ctx, self.stack.clone());
                 self.result = Some(res);
                 return Err(self.result.as_ref().unwrap());
@@ -493,63 +1203,105 @@ impl Exec {
 
         let exec = self.cond_stack.all_true();
         match instruction {
+            // Note that OP_0 is parsed as an empty push, so it pushes an empty
+            // byte string, while OP_1NEGATE and OP_1 .. OP_16 push numbers.
             Instruction::PushBytes(p) => {
                 if p.len() > MAX_SCRIPT_ELEMENT_SIZE {
                     return self.fail(ExecError::PushSize);
                 }
+                if exec && self.opt.require_minimal && self.opt.minimal_push {
+                    let op = Opcode::from(self.script.as_bytes()[self.current_position]);
+                    if !utils::check_minimal_push(p.as_bytes(), op) {
+                        return self.failop(ExecError::MinimalData, op);
+                    }
+                }
                 if exec {
+                    let op = Opcode::from(self.script.as_bytes()[self.current_position]);
+                    self.charge(op);
                     self.stack.pushstr(p.as_bytes());
+                    if let Some(ref mut taint) = self.taint {
+                        taint.push();
+                    }
                 }
             }
             Instruction::Op(op) => {
                 // Some things we do even when we're not executing.
 
                 // Note how OP_RESERVED does not count towards the opcode limit.
-                if self.ctx == ExecCtx::Legacy || self.ctx == ExecCtx::SegwitV0 {
-                    if op.to_u8() > OP_PUSHNUM_16.to_u8() {
-                        self.opcode_count += 1;
-                        if self.opcode_count > MAX_OPS_PER_SCRIPT {
-                            return self.fail(ExecError::OpCount);
-                        }
+                if (self.ctx == ExecCtx::Legacy || self.ctx == ExecCtx::SegwitV0)
+                    && op.to_u8() > OP_PUSHNUM_16.to_u8()
+                {
+                    self.opcode_count += 1;
+                    if self.opcode_count > MAX_OPS_PER_SCRIPT {
+                        return self.fail(ExecError::OpCount);
                     }
                 }
 
-                match op {
-                    OP_CAT if !self.opt.experimental.op_cat || self.ctx != ExecCtx::Tapscript => {
-                        return self.failop(ExecError::DisabledOpcode, op);
-                    }
-                    OP_MUL if !self.opt.experimental.op_mul || self.ctx != ExecCtx::Tapscript => {
-                        return self.failop(ExecError::DisabledOpcode, op);
-                    }
-                    OP_DIV if !self.opt.experimental.op_div || self.ctx != ExecCtx::Tapscript => {
-                        return self.failop(ExecError::DisabledOpcode, op);
-                    }
-                    OP_SUBSTR | OP_LEFT | OP_RIGHT | OP_INVERT | OP_AND | OP_OR | OP_XOR
-                    | OP_2MUL | OP_2DIV | OP_MOD | OP_LSHIFT | OP_RSHIFT => {
-                        return self.failop(ExecError::DisabledOpcode, op);
-                    }
-                    OP_RESERVED => {
-                        return self.failop(ExecError::Debug, op);
+                let custom = self.custom_opcodes.contains_key(&op.to_u8());
+                // OP_RESERVED, OP_VER and OP_RESERVED1/2 are left to exec_opcode,
+                // which fails them with BadOpcode only in executed branches.
+                if !custom {
+                    if let Some(err) = disabled_opcode_error(op, self.ctx, &self.opt) {
+                        return self.failop(err, op);
                     }
+                }
 
-                    _ => {}
+                if exec
+                    && op == OP_RETURN
+                    && !custom
+                    && self.opt.op_return_policy == OpReturnPolicy::StopSuccess
+                {
+                    self.op_return_pos = Some(self.script_position());
+                    self.result = Some(ExecutionResult {
+                        success: true,
+                        error: None,
+                        opcode: Some(op),
+                        final_stack: self.stack.clone(),
+                    });
+                    return Err(self.result.as_ref().unwrap());
                 }
 
                 if exec || (op.to_u8() >= OP_IF.to_u8() && op.to_u8() <= OP_ENDIF.to_u8()) {
+                    self.charge(op);
+                    let taint_pre = self.taint.as_ref().map(|_| TaintPre {
+                        depth: self.stack.len(),
+                        n: match op {
+                            OP_PICK | OP_ROLL if exec => {
+                                self.topnum(-1).ok().and_then(|n| usize::try_from(n).ok())
+                            }
+                            _ => None,
+                        },
+                    });
                     if let Err(err) = self.exec_opcode(op) {
                         return self.failop(err, op);
                     }
+                    if let (Some(taint), Some(pre)) = (self.taint.as_mut(), taint_pre) {
+                        let (depth, altdepth) = (self.stack.len(), self.altstack.len());
+                        taint.apply(op, exec, custom, pre, depth, altdepth);
+                    }
                 }
             }
         }
 
         self.update_stats();
+        self.record_history();
         Ok(())
     }
 
     fn exec_opcode(&mut self, op: Opcode) -> Result<(), ExecError> {
         let exec = self.cond_stack.all_true();
 
+        if let Some(handler) = self.custom_opcodes.get(&op.to_u8()) {
+            if exec {
+                handler(&mut ExecState {
+                    ctx: self.ctx,
+                    stack: &mut self.stack,
+                    altstack: &mut self.altstack,
+                })?;
+            }
+            return self.check_stack_size();
+        }
+
         // Remember to leave stack intact until all errors have occurred.
         match op {
             //
@@ -562,6 +1314,63 @@ impl Exec {
                 self.stack.pushnum((n as i64) - 1);
             }
 
+            OP_NOP | OP_CLTV | OP_CSV | OP_NOP1 | OP_NOP4 | OP_NOP5 | OP_NOP6 | OP_NOP7
+            | OP_NOP8 | OP_NOP9 | OP_NOP10 | OP_IF | OP_NOTIF | OP_ELSE | OP_ENDIF | OP_VERIFY
+            | OP_RETURN => self.exec_flow(op)?,
+
+            OP_TOALTSTACK | OP_FROMALTSTACK | OP_2DROP | OP_2DUP | OP_3DUP | OP_2OVER | OP_2ROT
+            | OP_2SWAP | OP_IFDUP | OP_DEPTH | OP_DROP | OP_DUP | OP_NIP | OP_OVER | OP_PICK
+            | OP_ROLL | OP_ROT | OP_SWAP | OP_TUCK | OP_CAT | OP_SIZE => self.exec_stack_ops(op)?,
+
+            OP_EQUAL
+            | OP_EQUALVERIFY
+            | OP_1ADD
+            | OP_1SUB
+            | OP_NEGATE
+            | OP_ABS
+            | OP_NOT
+            | OP_0NOTEQUAL
+            | OP_ADD
+            | OP_SUB
+            | OP_MUL
+            | OP_DIV
+            | OP_BOOLAND
+            | OP_BOOLOR
+            | OP_NUMEQUAL
+            | OP_NUMEQUALVERIFY
+            | OP_NUMNOTEQUAL
+            | OP_LESSTHAN
+            | OP_GREATERTHAN
+            | OP_LESSTHANOREQUAL
+            | OP_GREATERTHANOREQUAL
+            | OP_MIN
+            | OP_MAX
+            | OP_WITHIN => self.exec_arithmetic(op)?,
+
+            OP_RIPEMD160
+            | OP_SHA1
+            | OP_SHA256
+            | OP_HASH160
+            | OP_HASH256
+            | OP_CODESEPARATOR
+            | OP_CHECKSIG
+            | OP_CHECKSIGVERIFY
+            | OP_CHECKSIGADD
+            | OP_CHECKMULTISIG
+            | OP_CHECKMULTISIGVERIFY => self.exec_crypto(op)?,
+
+            // remainder
+            _ => return Err(ExecError::BadOpcode),
+        }
+
+        self.check_stack_size()
+    }
+
+    /// Executes the flow control opcodes, including the NOPs and timelocks.
+    fn exec_flow(&mut self, op: Opcode) -> Result<(), ExecError> {
+        let exec = self.cond_stack.all_true();
+
+        match op {
             //
             // Control
             OP_NOP => {}
@@ -583,7 +1392,11 @@ impl Exec {
                 // Thus as a special case we tell CScriptNum to accept up
                 // to 5-byte bignums, which are good until 2**39-1, well
                 // beyond the 2**32-1 limit of the nLockTime field itself.
-                let n = read_scriptint(&top, 5, self.opt.require_minimal)?;
+                let n = read_scriptint(
+                    &top,
+                    5,
+                    self.opt.require_minimal && self.opt.minimal_locktime,
+                )?;
 
                 if n < 0 {
                     return Err(ExecError::NegativeLocktime);
@@ -601,7 +1414,11 @@ impl Exec {
                 // nSequence, like nLockTime, is a 32-bit unsigned integer
                 // field. See the comment in CHECKLOCKTIMEVERIFY regarding
                 // 5-byte numeric operands.
-                let n = read_scriptint(&top, 5, self.opt.require_minimal)?;
+                let n = read_scriptint(
+                    &top,
+                    5,
+                    self.opt.require_minimal && self.opt.minimal_locktime,
+                )?;
 
                 if n < 0 {
                     return Err(ExecError::NegativeLocktime);
@@ -609,10 +1426,8 @@ impl Exec {
 
                 //TODO(stevenroose) check this logic
                 //TODO(stevenroose) check if this cast is ok
-                if n & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 == 0 {
-                    if !self.check_sequence(n) {
-                        return Err(ExecError::UnsatisfiedLocktime);
-                    }
+                if n & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 == 0 && !self.check_sequence(n) {
+                    return Err(ExecError::UnsatisfiedLocktime);
                 }
             }
             OP_CSV => {} // otherwise nop
@@ -626,18 +1441,13 @@ impl Exec {
                     let top = self.stack.topstr(-1)?;
 
                     // Tapscript requires minimal IF/NOTIF inputs as a consensus rule.
-                    if self.ctx == ExecCtx::Tapscript {
-                        // The input argument to the OP_IF and OP_NOTIF opcodes must be either
-                        // exactly 0 (the empty vector) or exactly 1 (the one-byte vector with value 1).
-                        if top.len() > 1 || (top.len() == 1 && top[0] != 1) {
-                            return Err(ExecError::TapscriptMinimalIf);
-                        }
-                    }
                     // Under segwit v0 only enabled as policy.
-                    if self.opt.verify_minimal_if && self.ctx == ExecCtx::SegwitV0 {
-                        if top.len() > 1 || (top.len() == 1 && top[0] != 1) {
-                            return Err(ExecError::TapscriptMinimalIf);
-                        }
+                    let minimal_if = self.ctx == ExecCtx::Tapscript
+                        || (self.opt.verify_minimal_if && self.ctx == ExecCtx::SegwitV0);
+                    // The input argument to the OP_IF and OP_NOTIF opcodes must be either
+                    // exactly 0 (the empty vector) or exactly 1 (the one-byte vector with value 1).
+                    if minimal_if && (top.len() > 1 || (top.len() == 1 && top[0] != 1)) {
+                        return Err(ExecError::TapscriptMinimalIf);
                     }
                     let b = if op == OP_NOTIF {
                         !script::read_scriptbool(&top)
@@ -663,7 +1473,11 @@ impl Exec {
                 }
             }
 
+            // Like in Core, the verify-family opcodes behave like their plain
+            // counterpart followed by OP_VERIFY: on failure, the operands are
+            // consumed and the false result is left on top of the stack.
             OP_VERIFY => {
+                // The false element is not popped on failure.
                 let top = self.stack.topstr(-1)?;
 
                 if !script::read_scriptbool(&top) {
@@ -673,8 +1487,18 @@ impl Exec {
                 }
             }
 
+            OP_RETURN if self.opt.op_return_policy == OpReturnPolicy::Ignore => {}
             OP_RETURN => return Err(ExecError::OpReturn),
 
+            _ => return Err(ExecError::BadOpcode),
+        }
+
+        Ok(())
+    }
+
+    /// Executes the stack and splice opcodes.
+    fn exec_stack_ops(&mut self, op: Opcode) -> Result<(), ExecError> {
+        match op {
             //
             // Stack operations
             OP_TOALTSTACK => {
@@ -690,29 +1514,9 @@ impl Exec {
                 self.stack.push(top);
             }
 
-            OP_2DROP => {
-                // (x1 x2 -- )
-                self.stack.needn(2)?;
-                self.stack.popn(2).unwrap();
-            }
-
-            OP_2DUP => {
-                // (x1 x2 -- x1 x2 x1 x2)
-                let x1 = self.stack.top(-2)?.clone();
-                let x2 = self.stack.top(-1)?.clone();
-                self.stack.push(x1);
-                self.stack.push(x2);
-            }
-
-            OP_3DUP => {
-                // (x1 x2 x3 -- x1 x2 x3 x1 x2 x3)
-                let x1 = self.stack.top(-3)?.clone();
-                let x2 = self.stack.top(-2)?.clone();
-                let x3 = self.stack.top(-1)?.clone();
-                self.stack.push(x1);
-                self.stack.push(x2);
-                self.stack.push(x3);
-            }
+            OP_2DROP => self.stack.drop2()?,
+            OP_2DUP => self.stack.dup2()?,
+            OP_3DUP => self.stack.dup3()?,
 
             OP_2OVER => {
                 // (x1 x2 x3 x4 -- x1 x2 x3 x4 x1 x2)
@@ -723,35 +1527,8 @@ impl Exec {
                 self.stack.push(x2);
             }
 
-            OP_2ROT => {
-                // (x1 x2 x3 x4 x5 x6 -- x3 x4 x5 x6 x1 x2)
-                self.stack.needn(6)?;
-                let x6 = self.stack.pop().unwrap();
-                let x5 = self.stack.pop().unwrap();
-                let x4 = self.stack.pop().unwrap();
-                let x3 = self.stack.pop().unwrap();
-                let x2 = self.stack.pop().unwrap();
-                let x1 = self.stack.pop().unwrap();
-                self.stack.push(x3);
-                self.stack.push(x4);
-                self.stack.push(x5);
-                self.stack.push(x6);
-                self.stack.push(x1);
-                self.stack.push(x2);
-            }
-
-            OP_2SWAP => {
-                // (x1 x2 x3 x4 -- x3 x4 x1 x2)
-                self.stack.needn(4)?;
-                let x4 = self.stack.pop().unwrap();
-                let x3 = self.stack.pop().unwrap();
-                let x2 = self.stack.pop().unwrap();
-                let x1 = self.stack.pop().unwrap();
-                self.stack.push(x3);
-                self.stack.push(x4);
-                self.stack.push(x1);
-                self.stack.push(x2);
-            }
+            OP_2ROT => self.stack.rot2()?,
+            OP_2SWAP => self.stack.swap2()?,
 
             OP_IFDUP => {
                 // (x - 0 | x x)
@@ -766,12 +1543,7 @@ impl Exec {
                 self.stack.pushnum(self.stack.len() as i64);
             }
 
-            OP_DROP => {
-                // (x -- )
-                if self.stack.pop().is_none() {
-                    return Err(ExecError::InvalidStackOperation);
-                }
-            }
+            OP_DROP => self.stack.drop()?,
 
             OP_DUP => {
                 // (x -- x x)
@@ -779,27 +1551,24 @@ impl Exec {
                 self.stack.push(top);
             }
 
-            OP_NIP => {
-                // (x1 x2 -- x2)
-                self.stack.needn(2)?;
-                let x2 = self.stack.pop().unwrap();
-                self.stack.pop().unwrap();
-                self.stack.push(x2);
-            }
-
-            OP_OVER => {
-                // (x1 x2 -- x1 x2 x1)
-                let under_top = self.stack.top(-2)?.clone();
-                self.stack.push(under_top);
-            }
+            OP_NIP => self.stack.nip()?,
+            OP_OVER => self.stack.over()?,
 
             OP_PICK | OP_ROLL => {
                 // (xn ... x2 x1 x0 n - xn ... x2 x1 x0 xn)
                 // (xn ... x2 x1 x0 n - ... x2 x1 x0 xn)
-                let x = self.stack.topnum(-1, self.opt.require_minimal)?;
-                if x < 0 || x >= self.stack.len() as i64 {
+                let x = self.topnum(-1)?;
+                if x < 0 || x >= self.stack.len() as i64 - 1 {
                     return Err(ExecError::InvalidStackOperation);
                 }
+                if op == OP_ROLL {
+                    self.roll_displacement += x as usize;
+                    if let Some(max) = self.opt.limits.max_roll_displacement {
+                        if self.roll_displacement > max {
+                            return Err(ExecError::ResourceExhausted);
+                        }
+                    }
+                }
                 self.stack.pop().unwrap();
                 let elem = self.stack.top(-x as isize - 1).unwrap().clone();
                 if op == OP_ROLL {
@@ -828,22 +1597,14 @@ impl Exec {
                 self.stack.push(x1);
             }
 
-            OP_TUCK => {
-                // (x1 x2 -- x2 x1 x2)
-                self.stack.needn(2)?;
-                let x2 = self.stack.pop().unwrap();
-                let x1 = self.stack.pop().unwrap();
-                self.stack.push(x2.clone());
-                self.stack.push(x1);
-                self.stack.push(x2);
-            }
+            OP_TUCK => self.stack.tuck()?,
 
             OP_CAT if self.opt.experimental.op_cat && self.ctx == ExecCtx::Tapscript => {
                 // (x1 x2 -- x1|x2)
                 self.stack.needn(2)?;
                 let x2 = self.stack.popstr().unwrap();
                 let x1 = self.stack.popstr().unwrap();
-                let ret: Vec<u8> = x1.into_iter().chain(x2.into_iter()).collect();
+                let ret: Vec<u8> = x1.into_iter().chain(x2).collect();
                 if ret.len() > MAX_SCRIPT_ELEMENT_SIZE {
                     return Err(ExecError::PushSize);
                 }
@@ -856,6 +1617,30 @@ impl Exec {
                 self.stack.pushnum(top.len() as i64);
             }
 
+            _ => return Err(ExecError::BadOpcode),
+        }
+
+        Ok(())
+    }
+
+    /// Executes the equality and numeric opcodes.
+    fn exec_arithmetic(&mut self, op: Opcode) -> Result<(), ExecError> {
+        let minimal = self.opt.require_minimal
+            && match op {
+                OP_NUMEQUAL
+                | OP_NUMEQUALVERIFY
+                | OP_NUMNOTEQUAL
+                | OP_LESSTHAN
+                | OP_GREATERTHAN
+                | OP_LESSTHANOREQUAL
+                | OP_GREATERTHANOREQUAL
+                | OP_MIN
+                | OP_MAX
+                | OP_WITHIN => self.opt.minimal_comparison,
+                _ => self.opt.minimal_arithmetic,
+            };
+
+        match op {
             //
             // Bitwise logic
             OP_EQUAL | OP_EQUALVERIFY => {
@@ -864,28 +1649,29 @@ impl Exec {
                 let x2 = self.stack.popstr().unwrap();
                 let x1 = self.stack.popstr().unwrap();
                 let equal = x1 == x2;
-                if op == OP_EQUALVERIFY && !equal {
-                    return Err(ExecError::EqualVerify);
-                }
-                if op == OP_EQUAL {
+                if op == OP_EQUAL || !equal {
                     let item = if equal { 1 } else { 0 };
                     self.stack.pushnum(item);
                 }
+                if op == OP_EQUALVERIFY && !equal {
+                    return Err(ExecError::EqualVerify);
+                }
             }
 
             //
             // Numeric
             OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
                 // (in -- out)
-                let x = self.stack.topnum(-1, self.opt.require_minimal)?;
+                // Operands are at most 4 bytes in sign-magnitude encoding, so x is
+                // in [-2^31+1, 2^31-1]: i32::MIN itself is not representable. Negating
+                // such a value can't overflow and results of OP_1ADD and OP_1SUB fit
+                // in the 5 bytes a result is allowed to take. With wider operands,
+                // results that don't fit in 8 bytes fail instead.
+                let x = self.topnum_minimal(-1, minimal)?;
                 let res = match op {
-                    OP_1ADD => x
-                        .checked_add(1)
-                        .ok_or(ExecError::ScriptIntNumericOverflow)?,
-                    OP_1SUB => x
-                        .checked_sub(1)
-                        .ok_or(ExecError::ScriptIntNumericOverflow)?,
-                    OP_NEGATE => x.checked_neg().ok_or(ExecError::ScriptIntNumericOverflow)?,
+                    OP_1ADD => checked_num(x.checked_add(1))?,
+                    OP_1SUB => checked_num(x.checked_sub(1))?,
+                    OP_NEGATE => checked_num(x.checked_neg())?,
                     OP_ABS => x.abs(),
                     OP_NOT => (x == 0) as i64,
                     OP_0NOTEQUAL => (x != 0) as i64,
@@ -909,15 +1695,11 @@ impl Exec {
             | OP_MIN
             | OP_MAX => {
                 // (x1 x2 -- out)
-                let x1 = self.stack.topnum(-2, self.opt.require_minimal)?;
-                let x2 = self.stack.topnum(-1, self.opt.require_minimal)?;
+                let x1 = self.topnum_minimal(-2, minimal)?;
+                let x2 = self.topnum_minimal(-1, minimal)?;
                 let res = match op {
-                    OP_ADD => x1
-                        .checked_add(x2)
-                        .ok_or(ExecError::ScriptIntNumericOverflow)?,
-                    OP_SUB => x1
-                        .checked_sub(x2)
-                        .ok_or(ExecError::ScriptIntNumericOverflow)?,
+                    OP_ADD => checked_num(x1.checked_add(x2))?,
+                    OP_SUB => checked_num(x1.checked_sub(x2))?,
                     OP_BOOLAND => (x1 != 0 && x2 != 0) as i64,
                     OP_BOOLOR => (x1 != 0 || x2 != 0) as i64,
                     OP_NUMEQUAL => (x1 == x2) as i64,
@@ -931,30 +1713,30 @@ impl Exec {
                     OP_MAX => cmp::max(x1, x2),
                     _ => unreachable!(),
                 };
-                if op == OP_NUMEQUALVERIFY && res == 0 {
-                    return Err(ExecError::NumEqualVerify);
-                }
                 self.stack.popn(2).unwrap();
-                if op != OP_NUMEQUALVERIFY {
+                if op != OP_NUMEQUALVERIFY || res == 0 {
                     self.stack.pushnum(res);
                 }
+                if op == OP_NUMEQUALVERIFY && res == 0 {
+                    return Err(ExecError::NumEqualVerify);
+                }
             }
 
             OP_MUL if self.opt.experimental.op_mul && self.ctx == ExecCtx::Tapscript => {
                 // (x1 x2 -- out)
-                let x1 = self.stack.topnum(-2, self.opt.require_minimal)?;
-                let x2 = self.stack.topnum(-1, self.opt.require_minimal)?;
+                let x1 = self.topnum_minimal(-2, minimal)?;
+                let x2 = self.topnum_minimal(-1, minimal)?;
 
-                self.stack.popn(2).unwrap();
+                let res = checked_num(x1.checked_mul(x2))?;
 
-                let res = x1 * x2;
+                self.stack.popn(2).unwrap();
                 self.stack.pushnum(res);
             }
 
             OP_DIV if self.opt.experimental.op_div && self.ctx == ExecCtx::Tapscript => {
                 // (x1 x2 -- out)
-                let x1 = self.stack.topnum(-2, self.opt.require_minimal)?;
-                let x2 = self.stack.topnum(-1, self.opt.require_minimal)?;
+                let x1 = self.topnum_minimal(-2, minimal)?;
+                let x2 = self.topnum_minimal(-1, minimal)?;
 
                 if x2 == 0 {
                     return Err(ExecError::DivByZero);
@@ -968,44 +1750,52 @@ impl Exec {
 
             OP_WITHIN => {
                 // (x min max -- out)
-                let x1 = self.stack.topnum(-3, self.opt.require_minimal)?;
-                let x2 = self.stack.topnum(-2, self.opt.require_minimal)?;
-                let x3 = self.stack.topnum(-1, self.opt.require_minimal)?;
+                let x1 = self.topnum_minimal(-3, minimal)?;
+                let x2 = self.topnum_minimal(-2, minimal)?;
+                let x3 = self.topnum_minimal(-1, minimal)?;
                 self.stack.popn(3).unwrap();
                 let res = x2 <= x1 && x1 < x3;
                 let item = if res { 1 } else { 0 };
                 self.stack.pushnum(item);
             }
 
+            _ => return Err(ExecError::BadOpcode),
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the top stack element by its hash.
+    ///
+    /// The element is hashed in place, so large elements aren't copied.
+    fn hash_top<const N: usize>(&mut self, hash: fn(&[u8]) -> [u8; N]) -> Result<(), ExecError> {
+        let ret = self.stack.top(-1)?.with_bytes(hash);
+        self.stack.drop()?;
+        self.stack.pushstr(&ret);
+        Ok(())
+    }
+
+    /// Executes the hashing and signature checking opcodes.
+    fn exec_crypto(&mut self, op: Opcode) -> Result<(), ExecError> {
+        if let OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 = op {
+            self.hashed_bytes += self.stack.top(-1)?.byte_len();
+            if let Some(max) = self.opt.limits.max_hash_bytes {
+                if self.hashed_bytes > max {
+                    return Err(ExecError::ResourceExhausted);
+                }
+            }
+        }
+
+        match op {
             //
             // Crypto
 
             // (in -- hash)
-            OP_RIPEMD160 => {
-                let top = self.stack.popstr()?;
-                self.stack
-                    .pushstr(&ripemd160::Hash::hash(&top[..]).to_byte_array().to_vec());
-            }
-            OP_SHA1 => {
-                let top = self.stack.popstr()?;
-                self.stack
-                    .pushstr(&sha1::Hash::hash(&top[..]).to_byte_array().to_vec());
-            }
-            OP_SHA256 => {
-                let top = self.stack.popstr()?;
-                self.stack
-                    .pushstr(&sha256::Hash::hash(&top[..]).to_byte_array().to_vec());
-            }
-            OP_HASH160 => {
-                let top = self.stack.popstr()?;
-                self.stack
-                    .pushstr(&hash160::Hash::hash(&top[..]).to_byte_array().to_vec());
-            }
-            OP_HASH256 => {
-                let top = self.stack.popstr()?;
-                self.stack
-                    .pushstr(&sha256d::Hash::hash(&top[..]).to_byte_array().to_vec());
-            }
+            OP_RIPEMD160 => self.hash_top(|b| ripemd160::Hash::hash(b).to_byte_array())?,
+            OP_SHA1 => self.hash_top(|b| sha1::Hash::hash(b).to_byte_array())?,
+            OP_SHA256 => self.hash_top(|b| sha256::Hash::hash(b).to_byte_array())?,
+            OP_HASH160 => self.hash_top(|b| hash160::Hash::hash(b).to_byte_array())?,
+            OP_HASH256 => self.hash_top(|b| sha256d::Hash::hash(b).to_byte_array())?,
 
             OP_CODESEPARATOR => {
                 // Store this CODESEPARATOR position and update the scriptcode.
@@ -1016,9 +1806,10 @@ impl Exec {
             OP_CHECKSIG | OP_CHECKSIGVERIFY => {
                 let sig = self.stack.topstr(-2)?.clone();
                 let pk = self.stack.topstr(-1)?.clone();
-                let res = self.check_sig(&sig, &pk)?;
+                let res = self.check_sig(&sig, &pk, op)?;
                 self.stack.popn(2).unwrap();
                 if op == OP_CHECKSIGVERIFY && !res {
+                    self.stack.pushnum(0);
                     return Err(ExecError::CheckSigVerify);
                 }
                 if op == OP_CHECKSIG {
@@ -1032,9 +1823,9 @@ impl Exec {
                     return Err(ExecError::BadOpcode);
                 }
                 let sig = self.stack.topstr(-3)?.clone();
-                let mut n = self.stack.topnum(-2, self.opt.require_minimal)?;
+                let mut n = self.topnum(-2)?;
                 let pk = self.stack.topstr(-1)?.clone();
-                let res = self.check_sig(&sig, &pk)?;
+                let res = self.check_sig(&sig, &pk, op)?;
                 self.stack.popn(3).unwrap();
                 if res {
                     n += 1;
@@ -1043,16 +1834,120 @@ impl Exec {
             }
 
             OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
-                unimplemented!();
+                // ([dummy] [sig ...] num_of_signatures [pubkey ...] num_of_pubkeys -- bool)
+                if self.ctx == ExecCtx::Tapscript {
+                    return Err(ExecError::TapscriptCheckMultiSig);
+                }
+
+                // The counts are always 4-byte numbers, regardless of the
+                // operand size allowed for arithmetic.
+                let mut i = 1;
+                let mut nb_keys = self.stack.topnum(-(i as isize), self.opt.require_minimal)?;
+                if !(0..=MAX_PUBKEYS_PER_MULTISIG).contains(&nb_keys) {
+                    return Err(ExecError::PubkeyCount);
+                }
+                self.opcode_count += nb_keys as usize;
+                if self.opcode_count > MAX_OPS_PER_SCRIPT {
+                    return Err(ExecError::OpCount);
+                }
+                i += 1;
+                let mut ikey = i;
+                i += nb_keys as usize;
+                let mut nb_sigs = self.stack.topnum(-(i as isize), self.opt.require_minimal)?;
+                if nb_sigs < 0 || nb_sigs > nb_keys {
+                    return Err(ExecError::SigCount);
+                }
+                i += 1;
+                let mut isig = i;
+                i += nb_sigs as usize;
+                let total_keys = nb_keys as usize;
+                let total_sigs = nb_sigs as usize;
+                let first_isig = isig;
+                // This includes the extra dummy element that is consumed
+                // because of a bug in the original implementation.
+                self.stack.needn(i)?;
+
+                // Drop the signatures in pre-segwit scripts but not segwit scripts
+                let mut scriptcode = Cow::Borrowed(self.script_code.as_bytes());
+                if self.ctx == ExecCtx::Legacy {
+                    for k in 0..nb_sigs as usize {
+                        let sig = self.stack.topstr(-((isig + k) as isize))?;
+                        find_and_delete(&mut scriptcode, &sig);
+                    }
+                }
+
+                let mut success = true;
+                while success && nb_sigs > 0 {
+                    let sig = self.stack.topstr(-(isig as isize))?;
+                    let pk = self.stack.topstr(-(ikey as isize))?;
+                    self.check_signature_encoding(&sig)?;
+                    self.check_pubkey_encoding(&pk)?;
+
+                    self.sigops += 1;
+                    let ok = !sig.is_empty() && self.check_sig_ecdsa(&sig, &pk, &scriptcode)?;
+                    // Keys and signatures are checked starting with the last ones.
+                    let key_index = total_keys - 1 - (ikey - 2);
+                    let sig_index = total_sigs - 1 - (isig - first_isig);
+                    self.record_sig_check(op, key_index, sig_index, &pk, ok);
+                    if ok {
+                        isig += 1;
+                        nb_sigs -= 1;
+                    }
+                    ikey += 1;
+                    nb_keys -= 1;
+
+                    // If there are more signatures left than keys left,
+                    // then too many signatures have failed.
+                    if nb_sigs > nb_keys {
+                        success = false;
+                    }
+                }
+
+                if !success && self.opt.null_fail {
+                    for k in first_isig..first_isig + total_sigs {
+                        if !self.stack.topstr(-(k as isize))?.is_empty() {
+                            return Err(ExecError::NullFail);
+                        }
+                    }
+                }
+
+                self.stack.popn(i).unwrap();
+                if op == OP_CHECKMULTISIGVERIFY && !success {
+                    self.stack.pushnum(0);
+                    return Err(ExecError::CheckMultiSigVerify);
+                }
+                if op == OP_CHECKMULTISIG {
+                    let ret = if success { 1 } else { 0 };
+                    self.stack.pushnum(ret);
+                }
             }
 
-            // remainder
             _ => return Err(ExecError::BadOpcode),
         }
 
+        Ok(())
+    }
+
+    /// Reads the number at [offset] from the top as a numeric operand.
+    fn topnum(&self, offset: isize) -> Result<i64, ExecError> {
+        self.topnum_minimal(offset, self.opt.require_minimal)
+    }
+
+    /// Like [Exec::topnum], with the minimality requirement given by the caller.
+    fn topnum_minimal(&self, offset: isize, require_minimal: bool) -> Result<i64, ExecError> {
+        self.stack
+            .topnum_sized(offset, self.opt.max_num_size, require_minimal)
+    }
+
+    fn check_stack_size(&self) -> Result<(), ExecError> {
         if self.opt.enforce_stack_limit && self.stack.len() + self.altstack.len() > MAX_STACK_SIZE {
             return Err(ExecError::StackSize);
         }
+        if let Some(max) = self.opt.limits.max_total_bytes {
+            if self.stack.byte_size() + self.altstack.byte_size() > max {
+                return Err(ExecError::ResourceExhausted);
+            }
+        }
 
         Ok(())
     }
@@ -1066,15 +1961,75 @@ impl Exec {
         self.stats.max_nb_stack_items = cmp::max(self.stats.max_nb_stack_items, stack_items);
 
         self.stats.opcode_count = self.opcode_count;
+        self.stats.sigops = self.sigops;
+        self.stats.hashed_bytes = self.hashed_bytes;
         self.stats.validation_weight = self.validation_weight;
     }
+
+    /// Adds the cost of [op] to the total cost.
+    fn charge(&mut self, op: Opcode) {
+        self.total_cost = self.total_cost.saturating_add(self.opt.cost_model.cost(op));
+    }
+
+    fn record_history(&mut self) {
+        if !self.opt.keep_history {
+            return;
+        }
+
+        if let Some(max) = self.opt.max_history {
+            if max == 0 {
+                return;
+            }
+            if self.history.len() >= max {
+                self.history.pop_front();
+            }
+        }
+        self.history.push_back(self.stack.snapshot());
+    }
+}
+
+/// Removes all pushes of [sig] from [script_code], like Bitcoin Core's
+/// `FindAndDelete(scriptCode, CScript() << sig)`.
+///
+/// Only whole instructions are removed, so the signature bytes inside another
+/// push or a push of [sig] with a longer length prefix are kept. Like in Core,
+/// an empty signature removes the OP_0s.
+fn find_and_delete(script_code: &mut Cow<[u8]>, sig: &[u8]) {
+    let mut pattern = ScriptBuf::new();
+    pattern.push_slice(<&script::PushBytes>::try_from(sig).expect("pushes are smaller than 4GB"));
+
+    let matches = utils::instructions_with_spans(Script::from_bytes(script_code))
+        .into_iter()
+        .map(|(span, _)| span)
+        .filter(|span| script_code[span.clone()] == *pattern.as_bytes())
+        .collect::<Vec<_>>();
+    if matches.is_empty() {
+        return;
+    }
+
+    let mut result = Vec::with_capacity(script_code.len());
+    let mut pos = 0;
+    for span in matches {
+        result.extend_from_slice(&script_code[pos..span.start]);
+        pos = span.end;
+    }
+    result.extend_from_slice(&script_code[pos..]);
+    *script_code = Cow::Owned(result);
+}
+
+/// Checks that [n] didn't overflow and can be encoded in at most 8 bytes.
+fn checked_num(n: Option<i64>) -> Result<i64, ExecError> {
+    n.filter(|n| *n != i64::MIN)
+        .ok_or(ExecError::ScriptIntNumericOverflow)
 }
 
 fn read_scriptint(item: &[u8], size: usize, minimal: bool) -> Result<i64, ExecError> {
     read_scriptint_size(item, size, minimal).map_err(|e| match e {
         ScriptIntError::NonMinimalPush => ExecError::MinimalData,
-        // only possible if size is 4 or lower
+        // the number is longer than the given size
         ScriptIntError::NumericOverflow => ExecError::ScriptIntNumericOverflow,
+        // we never read numbers larger than 8 bytes
+        ScriptIntError::MaxSizeTooLarge => ExecError::ScriptIntNumericOverflow,
     })
 }
 
@@ -1084,10 +2039,7 @@ pub fn convert_to_witness(script: ScriptBuf) -> Result<Vec<Vec<u8>>, Error> {
     let mut stack = vec![];
 
     for instruction in instructions {
-        if instruction.is_err() {
-            return Err(Error::InvalidScript(instruction.unwrap_err()));
-        }
-        let instruction = instruction.unwrap();
+        let instruction = instruction.map_err(Error::InvalidScript)?;
 
         match instruction {
             Instruction::PushBytes(p) => {
@@ -1170,8 +2122,10 @@ pub fn execute_script_with_witness(script: ScriptBuf, witness: Vec<Vec<u8>>) ->
 }
 
 pub fn execute_script_with_witness_unlimited_stack(script: ScriptBuf, witness: Vec<Vec<u8>>) -> crate::ExecuteInfo {
-    let mut opts = Options::default();
-    opts.enforce_stack_limit = false;
+    let opts = Options {
+        enforce_stack_limit: false,
+        ..Default::default()
+    };
 
     let mut exec = Exec::new(
         ExecCtx::Tapscript,
@@ -1219,6 +2173,112 @@ pub fn execute_script_with_witness_unlimited_stack(script: ScriptBuf, witness: V
     info
 }
 
+/// Parses [bytes] as a script and executes it in [ctx] with an empty initial stack.
+///
+/// The script is run against a dummy transaction with a single input spending
+/// an empty output, so any signature checks will fail.
+pub fn parse_and_exec(bytes: &[u8], ctx: ExecCtx, opt: Options) -> Result<ExecuteInfo, Error> {
+    let script = ScriptBuf::from_bytes(bytes.to_vec());
+    let tx = TxTemplate::dummy();
+    let mut exec = Exec::new(ctx, opt, tx, script, vec![])?;
+    loop {
+        if exec.exec_next().is_err() {
+            break;
+        }
+    }
+    let res = exec.result().unwrap();
+
+    Ok(ExecuteInfo {
+        success: res.success,
+        error: res.error.clone(),
+        last_opcode: res.opcode,
+        final_stack: FmtStack(exec.stack().clone()),
+        remaining_script: exec.remaining_script().to_asm_string(),
+        stats: exec.stats().clone(),
+    })
+}
+
+/// The outcome of [verify].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub accepted: bool,
+    pub error: Option<ExecError>,
+    pub final_stack: Stack,
+    /// The number of signature checks performed.
+    pub sigops: usize,
+}
+
+/// The error [op] fails with in [ctx] because it is disabled, even in
+/// unexecuted branches.
+pub(crate) fn disabled_opcode_error(op: Opcode, ctx: ExecCtx, opt: &Options) -> Option<ExecError> {
+    let disabled = match op {
+        OP_CAT => !opt.experimental.op_cat || ctx != ExecCtx::Tapscript,
+        OP_MUL => !opt.experimental.op_mul || ctx != ExecCtx::Tapscript,
+        OP_DIV => !opt.experimental.op_div || ctx != ExecCtx::Tapscript,
+        OP_SUBSTR | OP_LEFT | OP_RIGHT | OP_INVERT | OP_AND | OP_OR | OP_XOR | OP_2MUL
+        | OP_2DIV | OP_MOD | OP_LSHIFT | OP_RSHIFT => true,
+        _ => false,
+    };
+    disabled.then_some(ExecError::DisabledOpcode)
+}
+
+/// Executes [script] with [witness] as the initial stack and reports the outcome.
+///
+/// An empty script executes no opcodes and leaves the initial stack as is,
+/// so it is accepted exactly when [witness] by itself passes the final stack
+/// check of [ctx]; in particular, an empty script with an empty witness is
+/// rejected.
+pub fn verify(
+    ctx: ExecCtx,
+    opt: Options,
+    tx: TxTemplate,
+    script: ScriptBuf,
+    witness: Vec<Vec<u8>>,
+) -> Result<VerifyReport, Error> {
+    let mut exec = Exec::new(ctx, opt, tx, script, witness)?;
+    loop {
+        if exec.exec_next().is_err() {
+            break;
+        }
+    }
+    let res = exec.result().unwrap();
+
+    Ok(VerifyReport {
+        accepted: res.success,
+        error: res.error.clone(),
+        final_stack: res.final_stack.clone(),
+        sigops: exec.stats().sigops,
+    })
+}
+
+/// Runs [script_pubkey] in Tapscript once for each of [witnesses] and returns
+/// whether each witness was accepted.
+///
+/// Every run is independent and uses the same dummy transaction as
+/// [parse_and_exec], so this is meant for checking spending paths that don't
+/// depend on transaction data, like hashlocks or unknown key types. The runs
+/// share a single [Exec], reusing its script, stacks and sighash cache.
+pub fn verify_any(script_pubkey: &Script, witnesses: &[Vec<Vec<u8>>], opt: Options) -> Vec<bool> {
+    let exec = Exec::new(
+        ExecCtx::Tapscript,
+        opt,
+        TxTemplate::dummy(),
+        script_pubkey.to_owned(),
+        vec![],
+    );
+    let Ok(mut exec) = exec else {
+        return vec![false; witnesses.len()];
+    };
+    witnesses
+        .iter()
+        .map(|witness| {
+            exec.reset(witness.clone());
+            while exec.exec_next().is_ok() {}
+            exec.result().unwrap().success
+        })
+        .collect()
+}
+
 #[derive(Debug)]
 pub struct ExecuteInfo {
     pub success: bool,
@@ -1239,10 +2299,10 @@ impl std::fmt::Display for ExecuteInfo {
         if let Some(ref error) = self.error {
             writeln!(f, "Error: {:?}", error)?;
         }
-        if self.remaining_script.len() > 0 {
+        if !self.remaining_script.is_empty() {
             writeln!(f, "Remaining Script: {}", self.remaining_script)?;
         }
-        if self.final_stack.len() > 0 {
+        if !self.final_stack.is_empty() {
             match f.width() {
                 None => writeln!(f, "Final Stack: {:4}", self.final_stack)?,
                 Some(width) => {
@@ -1267,7 +2327,7 @@ impl std::fmt::Display for FmtStack {
         while let Some((index, item)) = iter.next() {
             write!(f, "0x{:8}", item.as_hex())?;
             if iter.peek().is_some() {
-                if (index + 1) % f.width().unwrap() == 0 {
+                if (index + 1) % f.width().unwrap_or(4) == 0 {
                     write!(f, "\n{}:\t\t", index + 1)?;
                 }
                 write!(f, " ")?;
@@ -1282,6 +2342,10 @@ impl FmtStack {
         self.0.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
     pub fn get(&self, index: usize) -> Vec<u8> {
         self.0.get(index)
     }
@@ -1293,3 +2357,1903 @@ impl std::fmt::Debug for FmtStack {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::script::Builder;
+
+    /// Runs [script] on [witness] against a dummy transaction.
+    fn run(ctx: ExecCtx, opt: Options, script: ScriptBuf, witness: Vec<Vec<u8>>) -> Exec {
+        let mut exec = Exec::new(ctx, opt, TxTemplate::dummy(), script, witness).unwrap();
+        while exec.exec_next().is_ok() {}
+        exec
+    }
+
+    #[test]
+    fn reserved_opcodes_fail_only_when_executed() {
+        for op in [OP_RESERVED, OP_VER, OP_RESERVED1, OP_RESERVED2] {
+            let skipped = Builder::new()
+                .push_opcode(OP_PUSHBYTES_0)
+                .push_opcode(OP_IF)
+                .push_opcode(op)
+                .push_opcode(OP_ENDIF)
+                .push_opcode(OP_PUSHNUM_1)
+                .into_script();
+            let res = run(ExecCtx::Legacy, Options::default(), skipped, vec![]);
+            assert!(res.result().unwrap().success, "{}", op);
+
+            let executed = Builder::new().push_opcode(op).into_script();
+            let exec = run(ExecCtx::Legacy, Options::default(), executed, vec![]);
+            let res = exec.result().unwrap();
+            assert_eq!(res.error, Some(ExecError::BadOpcode), "{}", op);
+            assert_eq!(res.opcode, Some(op));
+        }
+    }
+
+    #[test]
+    fn history_has_a_snapshot_per_opcode() {
+        let script = Builder::new()
+            .push_int(1)
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_ADD)
+            .push_int(2)
+            .push_opcode(OP_EQUAL)
+            .into_script();
+        let executed_opcodes = script.instructions().count();
+        let opt = Options {
+            keep_history: true,
+            ..Options::default()
+        };
+        let exec = run(ExecCtx::Legacy, opt.clone(), script.clone(), vec![]);
+        assert!(exec.result().unwrap().success);
+        assert_eq!(exec.history().len(), executed_opcodes + 1);
+        assert!(exec.history().next().unwrap().is_empty());
+        assert_eq!(exec.history().nth(4).unwrap().len(), 2);
+
+        let opt = Options {
+            max_history: Some(2),
+            ..opt
+        };
+        let exec = run(ExecCtx::Legacy, opt, script, vec![]);
+        assert_eq!(exec.history().len(), 2);
+        assert_eq!(exec.history().last().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn oversized_script_fails_at_load() {
+        let script = ScriptBuf::from(vec![OP_NOP.to_u8(); MAX_SCRIPT_SIZE + 1]);
+        for ctx in [ExecCtx::Legacy, ExecCtx::SegwitV0] {
+            let opt = Options::default();
+            let res = Exec::new(ctx, opt, TxTemplate::dummy(), script.clone(), vec![]);
+            assert!(
+                matches!(res, Err(Error::Exec(ExecError::ScriptSize))),
+                "{:?}",
+                ctx
+            );
+        }
+
+        // Tapscript has no consensus limit, only the configured one.
+        let opt = Options::default();
+        let exec = Exec::new(
+            ExecCtx::Tapscript,
+            opt,
+            TxTemplate::dummy(),
+            script.clone(),
+            vec![],
+        );
+        assert!(exec.is_ok());
+
+        let mut opt = Options::default();
+        opt.limits.max_script_size = Some(MAX_SCRIPT_SIZE);
+        let res = Exec::new(ExecCtx::Tapscript, opt, TxTemplate::dummy(), script, vec![]);
+        assert!(matches!(res, Err(Error::Exec(ExecError::ScriptSize))));
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn verify_any_two_of_three() {
+        use bitcoin::sighash::{Prevouts, TapSighashType};
+
+        let secp = secp256k1::Secp256k1::new();
+        let keys = [[1u8; 32], [2; 32], [3; 32]]
+            .map(|sk| secp256k1::Keypair::from_seckey_slice(&secp, &sk).unwrap());
+        let tx = TxTemplate::dummy();
+        let sighash = SighashCache::new(&tx.tx)
+            .taproot_script_spend_signature_hash(
+                0,
+                &Prevouts::All(&tx.prevouts),
+                TapLeafHash::all_zeros(),
+                TapSighashType::Default,
+            )
+            .unwrap();
+        let msg = secp256k1::Message::from_digest(sighash.to_byte_array());
+        let sigs = keys.map(|k| secp.sign_schnorr_no_aux_rand(&msg, &k).as_ref().to_vec());
+
+        let script = Builder::new()
+            .push_x_only_key(&keys[0].x_only_public_key().0)
+            .push_opcode(OP_CHECKSIG)
+            .push_x_only_key(&keys[1].x_only_public_key().0)
+            .push_opcode(OP_CHECKSIGADD)
+            .push_x_only_key(&keys[2].x_only_public_key().0)
+            .push_opcode(OP_CHECKSIGADD)
+            .push_int(2)
+            .push_opcode(OP_NUMEQUAL)
+            .into_script();
+
+        // The witness holds the signatures in reverse key order, as the first
+        // key is checked against the top of the stack.
+        let witness = |signers: [bool; 3]| -> Vec<Vec<u8>> {
+            (0..3)
+                .rev()
+                .map(|i| if signers[i] { sigs[i].clone() } else { vec![] })
+                .collect()
+        };
+        let witnesses = vec![
+            witness([true, true, false]),
+            witness([true, false, true]),
+            witness([false, true, true]),
+            witness([true, true, true]),
+            witness([true, false, false]),
+            witness([false, false, false]),
+            vec![vec![], sigs[0].clone(), sigs[1].clone()],
+        ];
+        assert_eq!(
+            verify_any(&script, &witnesses, Options::default()),
+            vec![true, true, true, false, false, false, false],
+        );
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn strict_der_rejects_malformed_signatures() {
+        let pk = [2; 33];
+        // r = 1 padded with a zero byte, s = 1, SIGHASH_ALL
+        let sig = [0x30, 0x07, 0x02, 0x02, 0x00, 0x01, 0x02, 0x01, 0x01, 0x01];
+        let script = Builder::new()
+            .push_slice(sig)
+            .push_slice(pk)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_NOT)
+            .into_script();
+
+        let exec = run(ExecCtx::Legacy, Options::default(), script.clone(), vec![]);
+        assert_eq!(exec.result().unwrap().error, Some(ExecError::SigDer));
+
+        let opt = Options {
+            strict_der: false,
+            ..Options::default()
+        };
+        let exec = run(ExecCtx::Legacy, opt, script, vec![]);
+        assert!(exec.result().unwrap().success);
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn null_fail_rejects_failing_signatures() {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        // A valid signature of another message.
+        let msg = secp256k1::Message::from_digest([1; 32]);
+        let mut sig = secp.sign_ecdsa(&msg, &sk).serialize_der().to_vec();
+        sig.push(0x01);
+        let script = Builder::new()
+            .push_slice(script::PushBytesBuf::try_from(sig).unwrap())
+            .push_slice(sk.public_key(&secp).serialize())
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_NOT)
+            .into_script();
+
+        let exec = run(ExecCtx::Legacy, Options::default(), script.clone(), vec![]);
+        assert!(exec.result().unwrap().success);
+
+        let opt = Options {
+            null_fail: true,
+            ..Options::default()
+        };
+        let exec = run(ExecCtx::Legacy, opt, script, vec![]);
+        assert_eq!(exec.result().unwrap().error, Some(ExecError::NullFail));
+    }
+
+    #[test]
+    fn find_and_delete_matches_core() {
+        let sig = [0xab; 10];
+        let delete = |script: ScriptBuf, sig: &[u8]| {
+            let mut code = Cow::Borrowed(script.as_bytes());
+            find_and_delete(&mut code, sig);
+            code.into_owned()
+        };
+
+        // Every push of the signature is removed, consecutive ones included.
+        let script = Builder::new()
+            .push_slice(sig)
+            .push_opcode(OP_DUP)
+            .push_slice(sig)
+            .push_slice(sig)
+            .push_opcode(OP_DROP)
+            .into_script();
+        assert_eq!(delete(script, &sig), vec![OP_DUP.to_u8(), OP_DROP.to_u8()]);
+
+        // The signature bytes inside a larger push are kept.
+        let mut data = vec![10];
+        data.extend(sig);
+        let data = script::PushBytesBuf::try_from(data).unwrap();
+        let script = Builder::new().push_slice(data).into_script();
+        assert_eq!(delete(script.clone(), &sig), script.to_bytes());
+
+        // A push with a non-minimal length prefix doesn't match.
+        let mut script = vec![OP_PUSHDATA1.to_u8(), 10];
+        script.extend(sig);
+        assert_eq!(delete(ScriptBuf::from(script.clone()), &sig), script);
+
+        // An empty signature removes the OP_0s, but not other pushes
+        // containing a zero byte.
+        let script = Builder::new()
+            .push_opcode(OP_PUSHBYTES_0)
+            .push_slice([0u8, 0])
+            .push_opcode(OP_PUSHBYTES_0)
+            .into_script();
+        assert_eq!(delete(script, &[]), vec![2, 0, 0]);
+    }
+
+    #[test]
+    fn checkmultisig_checks_counts() {
+        let error = |ctx, script: ScriptBuf, witness| {
+            run(ctx, Options::default(), script, witness)
+                .result()
+                .unwrap()
+                .error
+                .clone()
+        };
+        let keys = |n: i64| {
+            Builder::new()
+                .push_int(n)
+                .push_opcode(OP_CHECKMULTISIG)
+                .into_script()
+        };
+
+        assert_eq!(
+            error(ExecCtx::Legacy, keys(21), vec![]),
+            Some(ExecError::PubkeyCount)
+        );
+        assert_eq!(
+            error(ExecCtx::Legacy, keys(-1), vec![]),
+            Some(ExecError::PubkeyCount)
+        );
+        assert_eq!(
+            error(ExecCtx::Tapscript, keys(0), vec![]),
+            Some(ExecError::TapscriptCheckMultiSig),
+        );
+
+        let script = Builder::new()
+            .push_int(2)
+            .push_slice([2; 33])
+            .push_int(1)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(
+            error(ExecCtx::Legacy, script, vec![]),
+            Some(ExecError::SigCount)
+        );
+
+        // The dummy element below the signatures is required and consumed.
+        let script = Builder::new()
+            .push_int(0)
+            .push_int(0)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        let exec = run(
+            ExecCtx::Legacy,
+            Options::default(),
+            script.clone(),
+            vec![vec![]],
+        );
+        assert!(exec.result().unwrap().success);
+        let res = error(ExecCtx::Legacy, script, vec![]);
+        assert_eq!(res, Some(ExecError::InvalidStackOperation));
+
+        // An empty signature fails the check without failing the script.
+        let script = Builder::new()
+            .push_int(1)
+            .push_slice([2; 33])
+            .push_int(1)
+            .push_opcode(OP_CHECKMULTISIG)
+            .push_opcode(OP_NOT)
+            .into_script();
+        let exec = run(
+            ExecCtx::Legacy,
+            Options::default(),
+            script,
+            vec![vec![], vec![]],
+        );
+        assert!(exec.result().unwrap().success);
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn checkmultisig_one_of_two() {
+        let secp = secp256k1::Secp256k1::new();
+        let keys = [[1u8; 32], [2; 32]].map(|sk| secp256k1::SecretKey::from_slice(&sk).unwrap());
+        let script = Builder::new()
+            .push_int(1)
+            .push_slice(keys[0].public_key(&secp).serialize())
+            .push_slice(keys[1].public_key(&secp).serialize())
+            .push_int(2)
+            .push_opcode(OP_CHECKMULTISIGVERIFY)
+            .push_int(1)
+            .into_script();
+        let tx = TxTemplate::dummy();
+        let sighash = SighashCache::new(&tx.tx)
+            .legacy_signature_hash(0, &script, 1)
+            .unwrap();
+        let msg = secp256k1::Message::from_digest(sighash.to_byte_array());
+        let sign = |sk: &secp256k1::SecretKey| {
+            let mut sig = secp.sign_ecdsa(&msg, sk).serialize_der().to_vec();
+            sig.push(0x01);
+            sig
+        };
+
+        for sk in &keys {
+            let witness = vec![vec![], sign(sk)];
+            let exec = run(ExecCtx::Legacy, Options::default(), script.clone(), witness);
+            assert!(exec.result().unwrap().success);
+        }
+
+        let other = secp256k1::SecretKey::from_slice(&[3; 32]).unwrap();
+        let exec = run(
+            ExecCtx::Legacy,
+            Options::default(),
+            script,
+            vec![vec![], sign(&other)],
+        );
+        assert_eq!(
+            exec.result().unwrap().error,
+            Some(ExecError::CheckMultiSigVerify)
+        );
+    }
+
+    #[test]
+    fn pick_and_roll_depth_excludes_the_index() {
+        for op in [OP_PICK, OP_ROLL] {
+            let script = |n| Builder::new().push_int(n).push_opcode(op).into_script();
+            let witness = vec![vec![1], vec![2]];
+            let exec = run(
+                ExecCtx::Legacy,
+                Options::default(),
+                script(1),
+                witness.clone(),
+            );
+            let res = exec.result().unwrap();
+            assert_eq!(res.error, None, "{}", op);
+            assert_eq!(res.final_stack.last().unwrap(), vec![1], "{}", op);
+
+            let exec = run(ExecCtx::Legacy, Options::default(), script(2), witness);
+            let res = exec.result().unwrap();
+            assert_eq!(res.error, Some(ExecError::InvalidStackOperation), "{}", op);
+        }
+    }
+
+    #[test]
+    fn current_instruction_follows_execution() {
+        let script = Builder::new()
+            .push_int(1)
+            .push_int(2)
+            .push_opcode(OP_ADD)
+            .push_int(3)
+            .push_opcode(OP_EQUAL)
+            .into_script();
+        let instructions = script
+            .instructions()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let tx = TxTemplate::dummy();
+        let mut exec = Exec::new(
+            ExecCtx::Legacy,
+            Options::default(),
+            tx,
+            script.clone(),
+            vec![],
+        )
+        .unwrap();
+        for (n, ins) in instructions.into_iter().enumerate() {
+            assert_eq!(exec.current_instruction(), Some(ins), "after {} steps", n);
+            exec.exec_next().unwrap();
+        }
+        assert_eq!(exec.current_instruction(), None);
+    }
+
+    #[test]
+    fn progress_goes_from_start_to_end() {
+        let script = Builder::new()
+            .push_int(1)
+            .push_opcode(OP_DUP)
+            .push_slice([7; 20])
+            .into_script();
+        let len = script.len();
+        let tx = TxTemplate::dummy();
+        let mut exec = Exec::new(ExecCtx::Legacy, Options::default(), tx, script, vec![]).unwrap();
+        assert_eq!(exec.progress(), (0, len));
+        while exec.exec_next().is_ok() {}
+        assert_eq!(exec.progress(), (len, len));
+    }
+
+    #[test]
+    fn verify_opcodes_fail_with_their_own_error() {
+        let cases = [
+            (OP_VERIFY, ExecError::Verify),
+            (OP_EQUALVERIFY, ExecError::EqualVerify),
+            (OP_NUMEQUALVERIFY, ExecError::NumEqualVerify),
+        ];
+        for (op, err) in cases {
+            let script = Builder::new()
+                .push_int(1)
+                .push_int(0)
+                .push_opcode(op)
+                .into_script();
+            let exec = run(ExecCtx::Legacy, Options::default(), script, vec![]);
+            let res = exec.result().unwrap();
+            assert_eq!(res.error, Some(err), "{}", op);
+            assert_eq!(res.opcode, Some(op));
+        }
+
+        // An empty signature fails the check without any crypto.
+        let script = Builder::new()
+            .push_slice([])
+            .push_slice([2; 33])
+            .push_opcode(OP_CHECKSIGVERIFY)
+            .into_script();
+        let exec = run(ExecCtx::Legacy, Options::default(), script, vec![]);
+        let res = exec.result().unwrap();
+        assert_eq!(res.error, Some(ExecError::CheckSigVerify));
+        assert_eq!(res.opcode, Some(OP_CHECKSIGVERIFY));
+
+        // The same for a 1-of-1 multisig, after the dummy element.
+        let script = Builder::new()
+            .push_slice([])
+            .push_slice([])
+            .push_int(1)
+            .push_slice([2; 33])
+            .push_int(1)
+            .push_opcode(OP_CHECKMULTISIGVERIFY)
+            .into_script();
+        let exec = run(ExecCtx::Legacy, Options::default(), script, vec![]);
+        let res = exec.result().unwrap();
+        assert_eq!(res.error, Some(ExecError::CheckMultiSigVerify));
+        assert_eq!(res.opcode, Some(OP_CHECKMULTISIGVERIFY));
+    }
+
+    #[test]
+    fn altstack_moves_entries() {
+        let script = Builder::new()
+            .push_int(5)
+            .push_opcode(OP_TOALTSTACK)
+            .push_opcode(OP_TOALTSTACK)
+            .into_script();
+        let tx = TxTemplate::dummy();
+        let mut exec = Exec::new(
+            ExecCtx::Legacy,
+            Options::default(),
+            tx,
+            script,
+            vec![vec![7]],
+        )
+        .unwrap();
+        let StackEntry::StrRef(bytes) = exec.stack().entries()[0].clone() else {
+            panic!()
+        };
+        while exec.exec_next().is_ok() {}
+        assert_eq!(exec.altstack().entries()[0], StackEntry::Num(5));
+        match &exec.altstack().entries()[1] {
+            StackEntry::StrRef(moved) => assert!(Rc::ptr_eq(moved, &bytes)),
+            StackEntry::Num(_) => panic!("byte string became a number"),
+        }
+
+        let script = Builder::new()
+            .push_int(5)
+            .push_opcode(OP_TOALTSTACK)
+            .push_opcode(OP_FROMALTSTACK)
+            .into_script();
+        let exec = run(ExecCtx::Legacy, Options::default(), script, vec![]);
+        assert_eq!(
+            exec.result().unwrap().final_stack.entries(),
+            &[StackEntry::Num(5)]
+        );
+
+        let script = Builder::new().push_opcode(OP_FROMALTSTACK).into_script();
+        let exec = run(ExecCtx::Legacy, Options::default(), script, vec![]);
+        let res = exec.result().unwrap();
+        assert_eq!(res.error, Some(ExecError::InvalidStackOperation));
+    }
+
+    #[test]
+    fn oversized_pushes_are_rejected() {
+        // An OP_PUSHDATA4 claiming 4GB fails parsing without allocating.
+        let script = ScriptBuf::from(vec![OP_PUSHDATA4.to_u8(), 0xff, 0xff, 0xff, 0xff, 1]);
+        let tx = TxTemplate::dummy();
+        let res = Exec::new(ExecCtx::Legacy, Options::default(), tx, script, vec![]);
+        assert!(matches!(res, Err(Error::InvalidScript(_))));
+
+        // A present push of more than 520 bytes fails with PushSize.
+        let mut script = vec![OP_PUSHDATA4.to_u8()];
+        script.extend(521u32.to_le_bytes());
+        script.extend([0; 521]);
+        let opt = Options {
+            minimal_push: false,
+            ..Options::default()
+        };
+        let exec = run(ExecCtx::Legacy, opt, ScriptBuf::from(script), vec![]);
+        assert_eq!(exec.result().unwrap().error, Some(ExecError::PushSize));
+    }
+
+    #[test]
+    fn minimal_push_option() {
+        // 0x17 pushed with OP_PUSHDATA1 instead of OP_PUSHBYTES_1.
+        let script = ScriptBuf::from(vec![OP_PUSHDATA1.to_u8(), 1, 0x17]);
+        let exec = run(ExecCtx::Legacy, Options::default(), script.clone(), vec![]);
+        assert_eq!(exec.result().unwrap().error, Some(ExecError::MinimalData));
+
+        let opt = Options {
+            minimal_push: false,
+            ..Options::default()
+        };
+        let exec = run(ExecCtx::Legacy, opt, script.clone(), vec![]);
+        assert!(exec.result().unwrap().success);
+
+        let opt = Options {
+            require_minimal: false,
+            ..Options::default()
+        };
+        let exec = run(ExecCtx::Legacy, opt, script, vec![]);
+        assert!(exec.result().unwrap().success);
+    }
+
+    #[test]
+    fn script_is_the_one_passed_in() {
+        let script = Builder::new()
+            .push_int(1)
+            .push_opcode(OP_VERIFY)
+            .into_script();
+        let tx = TxTemplate::dummy();
+        let exec = Exec::new(
+            ExecCtx::Legacy,
+            Options::default(),
+            tx,
+            script.clone(),
+            vec![],
+        );
+        let exec = exec.unwrap();
+        assert_eq!(exec.script(), script.as_script());
+        assert_eq!(exec.script().len(), 2);
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn sig_checks_point_at_the_bad_signature() {
+        let secp = secp256k1::Secp256k1::new();
+        let keys =
+            [[1u8; 32], [2; 32], [3; 32]].map(|sk| secp256k1::SecretKey::from_slice(&sk).unwrap());
+        let pubkeys = keys.map(|sk| sk.public_key(&secp).serialize());
+        let mut builder = Builder::new().push_int(2);
+        for pk in &pubkeys {
+            builder = builder.push_slice(pk);
+        }
+        let script = builder
+            .push_int(3)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        let tx = TxTemplate::dummy();
+        let sighash = SighashCache::new(&tx.tx)
+            .legacy_signature_hash(0, &script, 1)
+            .unwrap();
+        let sign = |sk: &secp256k1::SecretKey, digest: [u8; 32]| {
+            let msg = secp256k1::Message::from_digest(digest);
+            let mut sig = secp.sign_ecdsa(&msg, sk).serialize_der().to_vec();
+            sig.push(0x01);
+            sig
+        };
+
+        // The second signature, by the second key, signs another message.
+        let witness = vec![
+            vec![],
+            sign(&keys[0], sighash.to_byte_array()),
+            sign(&keys[1], [1; 32]),
+        ];
+        let opt = Options {
+            record_sig_checks: true,
+            ..Options::default()
+        };
+        let exec = run(ExecCtx::Legacy, opt, script, witness);
+        assert!(!exec.result().unwrap().success);
+        // The signatures are checked from the last one, which fails against
+        // the third and second key, leaving too few keys for the first one.
+        let checks = exec.sig_checks();
+        assert_eq!(checks.len(), 2);
+        for (check, key_index) in checks.iter().zip([2, 1]) {
+            assert_eq!(check.opcode, OP_CHECKMULTISIG);
+            assert_eq!(check.sig_index, 1);
+            assert_eq!(check.key_index, key_index);
+            assert_eq!(check.pubkey, pubkeys[key_index]);
+            assert!(!check.success);
+        }
+    }
+
+    #[test]
+    fn custom_opcode_handler() {
+        let op_double = Opcode::from(0xbb);
+        let script = Builder::new()
+            .push_int(21)
+            .push_opcode(op_double)
+            .push_int(42)
+            .push_opcode(OP_EQUAL)
+            .into_script();
+
+        let exec = run(ExecCtx::Legacy, Options::default(), script.clone(), vec![]);
+        assert_eq!(exec.result().unwrap().error, Some(ExecError::BadOpcode));
+
+        let tx = TxTemplate::dummy();
+        let mut exec = Exec::new(ExecCtx::Legacy, Options::default(), tx, script, vec![]).unwrap();
+        exec.register_opcode(
+            op_double,
+            Box::new(|state| {
+                let n = state.stack.popnum(true)?;
+                state.stack.pushnum(n * 2);
+                Ok(())
+            }),
+        );
+        while exec.exec_next().is_ok() {}
+        assert!(exec.result().unwrap().success);
+    }
+
+    #[test]
+    fn upgradable_nops_are_no_ops() {
+        for op in [OP_NOP1, OP_NOP4, OP_NOP9, OP_NOP10] {
+            let script = Builder::new().push_int(1).push_opcode(op).into_script();
+            let exec = run(ExecCtx::Legacy, Options::default(), script, vec![]);
+            let res = exec.result().unwrap();
+            assert!(res.success, "{}", op);
+            assert_eq!(res.final_stack.entries(), &[StackEntry::Num(1)], "{}", op);
+        }
+    }
+
+    #[test]
+    fn small_integer_pushes() {
+        let script = Builder::new()
+            .push_opcode(OP_PUSHBYTES_0)
+            .push_opcode(OP_PUSHNUM_NEG1)
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_PUSHNUM_16)
+            .into_script();
+        let tx = TxTemplate::dummy();
+        let mut exec = Exec::new(ExecCtx::Legacy, Options::default(), tx, script, vec![]).unwrap();
+        while exec.exec_next().is_ok() {}
+        let entries = exec.stack().entries();
+        assert!(matches!(&entries[0], StackEntry::StrRef(v) if v.borrow().is_empty()));
+        assert_eq!(
+            entries[1..],
+            [StackEntry::Num(-1), StackEntry::Num(1), StackEntry::Num(16)]
+        );
+    }
+
+    #[test]
+    fn verify_reports_every_field() {
+        let verify = |script: ScriptBuf| {
+            let tx = TxTemplate::dummy();
+            verify(
+                ExecCtx::Legacy,
+                Options::default(),
+                tx,
+                script,
+                vec![vec![7]],
+            )
+            .unwrap()
+        };
+
+        let script = Builder::new()
+            .push_int(7)
+            .push_opcode(OP_EQUAL)
+            .into_script();
+        let report = verify(script);
+        assert_eq!(
+            report,
+            VerifyReport {
+                accepted: true,
+                error: None,
+                final_stack: Stack::from_iter([1]),
+                sigops: 0,
+            }
+        );
+
+        let script = Builder::new()
+            .push_slice([])
+            .push_slice([2; 33])
+            .push_opcode(OP_CHECKSIGVERIFY)
+            .into_script();
+        let report = verify(script);
+        assert!(!report.accepted);
+        assert_eq!(report.error, Some(ExecError::CheckSigVerify));
+        assert_eq!(
+            report.final_stack.iter_str().collect::<Vec<_>>(),
+            vec![vec![7], vec![]]
+        );
+        // An empty signature fails without being checked.
+        assert_eq!(report.sigops, 0);
+    }
+
+    #[test]
+    fn failed_checksig_pushes_empty_false() {
+        for op in [OP_CHECKSIG, OP_CHECKSIGADD] {
+            let ctx = if op == OP_CHECKSIG {
+                ExecCtx::Legacy
+            } else {
+                ExecCtx::Tapscript
+            };
+            let mut builder = Builder::new().push_slice([]);
+            if op == OP_CHECKSIGADD {
+                builder = builder.push_int(0);
+            }
+            let script = builder.push_slice([2; 32]).push_opcode(op).into_script();
+            let exec = run(ctx, Options::default(), script, vec![]);
+            let top = exec.result().unwrap().final_stack.last().unwrap();
+            assert!(!script::read_scriptbool(&top), "{}", op);
+            assert!(top.is_empty(), "{}", op);
+        }
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn segwit_v0_sighash_commits_to_the_amount() {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let script = Builder::new()
+            .push_slice(sk.public_key(&secp).serialize())
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let with_amount = |sat| {
+            let mut tx = TxTemplate::dummy();
+            tx.prevouts[0].value = bitcoin::Amount::from_sat(sat);
+            tx
+        };
+
+        let tx = with_amount(1000);
+        let sighash = SighashCache::new(&tx.tx)
+            .p2wsh_signature_hash(
+                0,
+                &script,
+                tx.prevouts[0].value,
+                bitcoin::EcdsaSighashType::All,
+            )
+            .unwrap();
+        let msg = secp256k1::Message::from_digest(sighash.to_byte_array());
+        let mut sig = secp.sign_ecdsa(&msg, &sk).serialize_der().to_vec();
+        sig.push(0x01);
+
+        for (sat, accepted) in [(1000, true), (999, false)] {
+            let tx = with_amount(sat);
+            let mut exec = Exec::new(
+                ExecCtx::SegwitV0,
+                Options::default(),
+                tx,
+                script.clone(),
+                vec![sig.clone()],
+            )
+            .unwrap();
+            while exec.exec_next().is_ok() {}
+            assert_eq!(exec.result().unwrap().success, accepted, "{}", sat);
+        }
+
+        // The amount of the spent output is required.
+        let mut tx = TxTemplate::dummy();
+        tx.prevouts.clear();
+        let res = Exec::new(ExecCtx::SegwitV0, Options::default(), tx, script, vec![sig]);
+        assert!(matches!(res, Err(Error::Other(_))));
+    }
+
+    #[test]
+    fn state_reflects_mid_execution() {
+        let script = Builder::new()
+            .push_int(1)
+            .push_opcode(OP_TOALTSTACK)
+            .push_int(2)
+            .push_opcode(OP_PUSHBYTES_0)
+            .push_opcode(OP_IF)
+            .push_int(3)
+            .push_opcode(OP_ENDIF)
+            .into_script();
+        let tx = TxTemplate::dummy();
+        let mut exec = Exec::new(
+            ExecCtx::Legacy,
+            Options::default(),
+            tx,
+            script,
+            vec![vec![9]],
+        )
+        .unwrap();
+        for _ in 0..5 {
+            exec.exec_next().unwrap();
+        }
+        assert_eq!(
+            exec.state(),
+            MachineState {
+                stack: vec![vec![9], vec![2]],
+                altstack: vec![vec![1]],
+                cond_stack_depth: 1,
+                executing: false,
+                script_position: 5,
+                opcode_count: 2,
+                last_codeseparator_pos: None,
+            }
+        );
+    }
+
+    #[test]
+    fn op_return_policies() {
+        let script = Builder::new()
+            .push_int(1)
+            .push_opcode(OP_RETURN)
+            .push_slice(b"data")
+            .into_script();
+        let exec_with = |policy| {
+            let opt = Options {
+                op_return_policy: policy,
+                ..Options::default()
+            };
+            run(ExecCtx::Legacy, opt, script.clone(), vec![])
+        };
+
+        let exec = exec_with(OpReturnPolicy::Fail);
+        assert_eq!(exec.result().unwrap().error, Some(ExecError::OpReturn));
+        assert_eq!(exec.op_return_data(), None);
+
+        let exec = exec_with(OpReturnPolicy::StopSuccess);
+        let res = exec.result().unwrap();
+        assert!(res.success);
+        assert_eq!(res.final_stack.len(), 1);
+        assert_eq!(exec.op_return_data().unwrap().as_bytes(), b"\x04data");
+
+        // Execution continues past the OP_RETURN and pushes the data.
+        let exec = exec_with(OpReturnPolicy::Ignore);
+        let res = exec.result().unwrap();
+        assert_eq!(res.error, None);
+        assert_eq!(res.final_stack.len(), 2);
+    }
+
+    #[test]
+    fn parse_and_exec_witness_script() {
+        use bitcoin::hex::FromHex;
+
+        // OP_1 OP_2 OP_ADD OP_3 OP_EQUAL
+        let bytes = Vec::from_hex("5152935387").unwrap();
+        let info = parse_and_exec(&bytes, ExecCtx::SegwitV0, Options::default()).unwrap();
+        assert!(info.success);
+        assert_eq!(info.error, None);
+        assert_eq!(info.last_opcode, None);
+
+        // OP_1 OP_2 OP_ADD OP_4 OP_EQUALVERIFY
+        let bytes = Vec::from_hex("5152935488").unwrap();
+        let info = parse_and_exec(&bytes, ExecCtx::SegwitV0, Options::default()).unwrap();
+        assert_eq!(info.error, Some(ExecError::EqualVerify));
+        assert_eq!(info.last_opcode, Some(OP_EQUALVERIFY));
+
+        // A truncated OP_PUSHDATA1.
+        let res = parse_and_exec(&[0x4c], ExecCtx::SegwitV0, Options::default());
+        assert!(matches!(res, Err(Error::InvalidScript(_))));
+    }
+
+    #[test]
+    fn growth_loop_hits_the_byte_cap() {
+        let item = script::PushBytesBuf::try_from(vec![1; 500]).unwrap();
+        let mut builder = Builder::new().push_slice(item);
+        for _ in 0..30 {
+            builder = builder.push_opcode(OP_DUP);
+        }
+        let script = builder.into_script();
+
+        let exec = run(
+            ExecCtx::Tapscript,
+            Options::default(),
+            script.clone(),
+            vec![vec![2]],
+        );
+        assert_eq!(exec.result().unwrap().error, None);
+
+        let mut opt = Options::default();
+        opt.limits.max_total_bytes = Some(10_000);
+        let exec = run(ExecCtx::Tapscript, opt, script, vec![vec![2]]);
+        let res = exec.result().unwrap();
+        assert_eq!(res.error, Some(ExecError::ResourceExhausted));
+        assert!(exec.stack().byte_size() <= 10_000 + 500);
+    }
+
+    #[test]
+    fn unary_numeric_opcodes_at_the_extremes() {
+        let max = i32::MAX as i64;
+        let cases = [
+            (OP_ABS, max, max),
+            (OP_ABS, -max, max),
+            (OP_NEGATE, max, -max),
+            (OP_NEGATE, -max, max),
+            (OP_NOT, max, 0),
+            (OP_NOT, -max, 0),
+            (OP_NOT, 0, 1),
+            (OP_0NOTEQUAL, max, 1),
+            (OP_0NOTEQUAL, -max, 1),
+            (OP_0NOTEQUAL, 0, 0),
+        ];
+        for (op, x, expected) in cases {
+            let script = Builder::new().push_int(x).push_opcode(op).into_script();
+            let exec = run(ExecCtx::Legacy, Options::default(), script, vec![]);
+            assert_eq!(exec.result().unwrap().error, None, "{} {}", op, x);
+            assert_eq!(exec.stack().topnum(-1, true), Ok(expected), "{} {}", op, x);
+            if op == OP_NOT || op == OP_0NOTEQUAL {
+                // Booleans are minimally encoded, false is the empty string.
+                let top = exec.stack().iter_str().next_back().unwrap();
+                assert_eq!(top, if expected == 1 { vec![1] } else { vec![] });
+            }
+
+            // -2^31 is not a valid operand, it takes 5 bytes.
+            let script = Builder::new()
+                .push_int(-max - 1)
+                .push_opcode(op)
+                .into_script();
+            let exec = run(ExecCtx::Legacy, Options::default(), script, vec![]);
+            let res = exec.result().unwrap();
+            assert_eq!(
+                res.error,
+                Some(ExecError::ScriptIntNumericOverflow),
+                "{}",
+                op
+            );
+        }
+    }
+
+    #[test]
+    fn acceptance_of_clean_and_dirty_stacks() {
+        let one = Builder::new().push_opcode(OP_PUSHNUM_1).into_script();
+        let two = Builder::new()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_PUSHNUM_1)
+            .into_script();
+        let cases = [
+            (ExecCtx::Legacy, one.clone(), Acceptance::AcceptClean, true),
+            (ExecCtx::Legacy, two.clone(), Acceptance::AcceptDirty, true),
+            (ExecCtx::SegwitV0, one, Acceptance::AcceptClean, true),
+            (ExecCtx::SegwitV0, two, Acceptance::AcceptDirty, false),
+        ];
+        for (ctx, script, acceptance, success) in cases {
+            let exec = run(ctx, Options::default(), script, vec![]);
+            let res = exec.result().unwrap();
+            assert_eq!(res.acceptance(), acceptance);
+            assert_eq!(res.success, success);
+        }
+
+        let script = Builder::new()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_PUSHBYTES_0)
+            .into_script();
+        let exec = run(ExecCtx::Legacy, Options::default(), script, vec![]);
+        assert_eq!(exec.result().unwrap().acceptance(), Acceptance::Reject);
+        let script = Builder::new()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_RETURN)
+            .into_script();
+        let exec = run(ExecCtx::Legacy, Options::default(), script, vec![]);
+        assert_eq!(exec.result().unwrap().acceptance(), Acceptance::Reject);
+    }
+
+    #[test]
+    fn empty_script() {
+        for ctx in [ExecCtx::Legacy, ExecCtx::SegwitV0, ExecCtx::Tapscript] {
+            let exec = run(ctx, Options::default(), ScriptBuf::new(), vec![]);
+            let res = exec.result().unwrap();
+            assert_eq!(res.error, None);
+            assert!(!res.success);
+            assert!(res.final_stack.is_empty());
+
+            let exec = run(ctx, Options::default(), ScriptBuf::new(), vec![vec![1]]);
+            let res = exec.result().unwrap();
+            assert!(res.success);
+            assert_eq!(res.final_stack.iter_str().collect::<Vec<_>>(), [vec![1]]);
+        }
+    }
+
+    #[test]
+    fn failed_verify_leaves_false_on_the_stack() {
+        let cases = [
+            (OP_VERIFY, vec![vec![7], vec![1], vec![]]),
+            (OP_EQUALVERIFY, vec![vec![7], vec![]]),
+            (OP_NUMEQUALVERIFY, vec![vec![7], vec![]]),
+        ];
+        for (op, stack) in cases {
+            let script = Builder::new()
+                .push_int(1)
+                .push_int(0)
+                .push_opcode(op)
+                .into_script();
+            let tx = TxTemplate::dummy();
+            let mut exec = Exec::new(
+                ExecCtx::Legacy,
+                Options::default(),
+                tx,
+                script,
+                vec![vec![7]],
+            )
+            .unwrap();
+            while exec.exec_next().is_ok() {}
+            assert_eq!(exec.state().stack, stack, "{}", op);
+            assert_eq!(
+                exec.result()
+                    .unwrap()
+                    .final_stack
+                    .iter_str()
+                    .collect::<Vec<_>>(),
+                stack
+            );
+        }
+    }
+
+    #[test]
+    fn ifdup_copies_true_entries() {
+        let ifdup = Builder::new().push_opcode(OP_IFDUP).into_script();
+        for falsy in [vec![], vec![0], vec![0x80], vec![0, 0x80]] {
+            let exec = run(
+                ExecCtx::Legacy,
+                Options::default(),
+                ifdup.clone(),
+                vec![falsy],
+            );
+            assert_eq!(exec.stack().len(), 1);
+        }
+
+        let exec = run(ExecCtx::Legacy, Options::default(), ifdup, vec![vec![1]]);
+        let [StackEntry::StrRef(a), StackEntry::StrRef(b)] = exec.stack().entries() else {
+            panic!("unexpected stack: {:?}", exec.stack());
+        };
+        assert!(Rc::ptr_eq(a, b));
+
+        let script = Builder::new()
+            .push_int(5)
+            .push_opcode(OP_IFDUP)
+            .into_script();
+        let exec = run(ExecCtx::Legacy, Options::default(), script, vec![]);
+        assert_eq!(
+            exec.stack().entries(),
+            [StackEntry::Num(5), StackEntry::Num(5)]
+        );
+    }
+
+    #[test]
+    fn deeply_nested_conditionals() {
+        let depth = 5000;
+        let mut builder = Builder::new();
+        for _ in 0..depth {
+            builder = builder.push_opcode(OP_PUSHNUM_1).push_opcode(OP_IF);
+        }
+        builder = builder.push_opcode(OP_PUSHNUM_1);
+        for _ in 0..depth {
+            builder = builder.push_opcode(OP_ENDIF);
+        }
+        let opt = Options::default();
+        let exec = run(ExecCtx::Tapscript, opt, builder.into_script(), vec![]);
+        let res = exec.result().unwrap();
+        assert_eq!(res.error, None);
+        assert!(res.success);
+    }
+
+    #[test]
+    fn restore_returns_to_the_checkpoint() {
+        let script = Builder::new()
+            .push_int(2)
+            .push_opcode(OP_TOALTSTACK)
+            .push_int(3)
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_IF)
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_ADD)
+            .push_opcode(OP_ENDIF)
+            .push_opcode(OP_FROMALTSTACK)
+            .push_opcode(OP_ADD)
+            .into_script();
+        let tx = TxTemplate::dummy();
+        let mut exec = Exec::new(
+            ExecCtx::Legacy,
+            Options::default(),
+            tx,
+            script,
+            vec![vec![1]],
+        )
+        .unwrap();
+        for _ in 0..3 {
+            exec.exec_next().unwrap();
+        }
+        let cp = exec.checkpoint();
+        let state = exec.state();
+
+        while exec.exec_next().is_ok() {}
+        let result = exec.result().unwrap().clone();
+        assert_eq!(result.error, None);
+        assert_eq!(
+            result.final_stack.iter_str().collect::<Vec<_>>(),
+            [[1], [8]]
+        );
+        assert_ne!(exec.state(), state);
+
+        exec.restore(cp);
+        assert_eq!(exec.state(), state);
+        assert_eq!(exec.result(), None);
+        while exec.exec_next().is_ok() {}
+        assert_eq!(exec.result(), Some(&result));
+    }
+
+    #[test]
+    fn comparisons_mix_numbers_and_byte_strings() {
+        let cases = [
+            (OP_LESSTHAN, 2, 3, true),
+            (OP_LESSTHAN, 3, 3, false),
+            (OP_LESSTHANOREQUAL, 3, 3, true),
+            (OP_LESSTHANOREQUAL, 4, 3, false),
+            (OP_GREATERTHAN, 4, 3, true),
+            (OP_GREATERTHAN, 3, 3, false),
+            (OP_GREATERTHANOREQUAL, 3, 3, true),
+            (OP_GREATERTHANOREQUAL, 2, 3, false),
+            (OP_NUMEQUAL, 3, 3, true),
+            (OP_NUMEQUAL, 2, 3, false),
+            (OP_NUMNOTEQUAL, 2, 3, true),
+            (OP_NUMNOTEQUAL, 3, 3, false),
+            (OP_BOOLAND, 2, 3, true),
+            (OP_BOOLAND, 0, 3, false),
+            (OP_BOOLOR, 0, 3, true),
+        ];
+        for (op, a, b, expected) in cases {
+            let expected = if expected { vec![1] } else { vec![] };
+            // a is a byte string from the witness, b is a number.
+            let num_b = Builder::new().push_int(b).push_opcode(op).into_script();
+            // a is a number, b is a byte string from the witness.
+            let num_a = Builder::new()
+                .push_int(a)
+                .push_opcode(OP_SWAP)
+                .push_opcode(op)
+                .into_script();
+            for (script, witness) in [(num_b, a), (num_a, b)] {
+                let witness = vec![utils::scriptint_vec(witness)];
+                let exec = run(ExecCtx::Legacy, Options::default(), script, witness);
+                let res = exec.result().unwrap();
+                assert_eq!(res.error, None, "{} {} {}", op, a, b);
+                assert_eq!(
+                    res.final_stack.iter_str().collect::<Vec<_>>(),
+                    vec![expected.clone()]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn every_opcode_is_dispatched() {
+        let witness = vec![vec![1]; 6];
+        let supported = opcode_info::supported_opcodes();
+        for info in &supported {
+            if info.category == opcode_info::OpcodeCategory::Push {
+                continue;
+            }
+            let script = Builder::new().push_opcode(info.opcode).into_script();
+            let opt = Options::default_with_mul_div();
+            let exec = run(ExecCtx::Tapscript, opt, script, witness.clone());
+            let res = exec.result().unwrap();
+            assert_ne!(res.error, Some(ExecError::BadOpcode), "{}", info.opcode);
+        }
+
+        for byte in 0..=u8::MAX {
+            let op = Opcode::from(byte);
+            if supported.iter().any(|info| info.opcode == op) {
+                continue;
+            }
+            let script = Builder::new().push_opcode(op).into_script();
+            let exec = run(ExecCtx::Legacy, Options::default(), script, witness.clone());
+            let res = exec.result().unwrap();
+            assert!(
+                matches!(
+                    res.error,
+                    Some(ExecError::BadOpcode | ExecError::DisabledOpcode)
+                ),
+                "{}: {:?}",
+                op,
+                res.error
+            );
+        }
+    }
+
+    #[test]
+    fn wide_numeric_operands() {
+        let add = Builder::new().push_opcode(OP_ADD).into_script();
+        let five_bytes = utils::scriptint_vec(1 << 32);
+        assert_eq!(five_bytes.len(), 5);
+        let witness = vec![five_bytes.clone(), five_bytes];
+
+        let exec = run(
+            ExecCtx::Legacy,
+            Options::default(),
+            add.clone(),
+            witness.clone(),
+        );
+        let res = exec.result().unwrap();
+        assert_eq!(res.error, Some(ExecError::ScriptIntNumericOverflow));
+
+        let opt = Options {
+            max_num_size: 5,
+            ..Default::default()
+        };
+        let exec = run(ExecCtx::Legacy, opt, add, witness);
+        assert_eq!(exec.stack().topnum_sized(-1, 5, true), Ok(1 << 33));
+
+        let mut opt = Options {
+            max_num_size: 8,
+            ..Default::default()
+        };
+        let one_add = Builder::new().push_opcode(OP_1ADD).into_script();
+        let witness = vec![utils::scriptint_vec(i64::MAX - 1)];
+        let exec = run(ExecCtx::Legacy, opt.clone(), one_add.clone(), witness);
+        assert_eq!(exec.stack().topnum_sized(-1, 8, true), Ok(i64::MAX));
+        let witness = vec![utils::scriptint_vec(i64::MAX)];
+        let exec = run(ExecCtx::Legacy, opt.clone(), one_add, witness);
+        let res = exec.result().unwrap();
+        assert_eq!(res.error, Some(ExecError::ScriptIntNumericOverflow));
+
+        opt.max_num_size = 9;
+        let res = Exec::new(
+            ExecCtx::Legacy,
+            opt,
+            TxTemplate::dummy(),
+            ScriptBuf::new(),
+            vec![],
+        );
+        assert!(matches!(res, Err(Error::Other(_))));
+    }
+
+    #[test]
+    fn op_count_skips_pushes() {
+        let script = Builder::new()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_slice([1, 2])
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_PUSHBYTES_0)
+            .push_opcode(OP_IF)
+            .push_opcode(OP_DROP)
+            .push_opcode(OP_ENDIF)
+            .into_script();
+        let tx = TxTemplate::dummy();
+        let mut exec = Exec::new(
+            ExecCtx::Legacy,
+            Options::default(),
+            tx,
+            script.clone(),
+            vec![],
+        )
+        .unwrap();
+        // Opcodes in unexecuted branches count too.
+        for expected in [0, 0, 1, 1, 2, 3, 4] {
+            exec.exec_next().unwrap();
+            assert_eq!(exec.op_count(), expected);
+        }
+
+        let exec = run(ExecCtx::Tapscript, Options::default(), script, vec![]);
+        assert_eq!(exec.op_count(), 0);
+    }
+
+    #[test]
+    fn empty_signatures_need_no_crypto() {
+        let opt = Options {
+            null_fail: true,
+            ..Default::default()
+        };
+        let checksig = |sig: &[u8], pk_len| {
+            let sig = script::PushBytesBuf::try_from(sig.to_vec()).unwrap();
+            let pk = script::PushBytesBuf::try_from(vec![2; pk_len]).unwrap();
+            Builder::new()
+                .push_slice(sig)
+                .push_slice(pk)
+                .push_opcode(OP_CHECKSIG)
+                .push_opcode(OP_NOT)
+                .into_script()
+        };
+        let cases = [
+            (ExecCtx::Legacy, 33),
+            (ExecCtx::SegwitV0, 33),
+            (ExecCtx::Tapscript, 32),
+        ];
+        for (ctx, pk_len) in cases {
+            let exec = run(ctx, opt.clone(), checksig(&[], pk_len), vec![]);
+            let res = exec.result().unwrap();
+            assert_eq!(res.error, None, "{:?}", ctx);
+            assert!(res.success, "{:?}", ctx);
+        }
+
+        let script = Builder::new()
+            .push_int(0)
+            .push_slice([])
+            .push_int(1)
+            .push_slice([2; 33])
+            .push_int(1)
+            .push_opcode(OP_CHECKMULTISIG)
+            .push_opcode(OP_NOT)
+            .into_script();
+        let exec = run(ExecCtx::Legacy, opt.clone(), script, vec![]);
+        assert!(exec.result().unwrap().success);
+
+        // Other signatures need the verify feature.
+        #[cfg(not(feature = "verify"))]
+        {
+            let der = [0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x02, 0x01];
+            let exec = run(ExecCtx::Legacy, opt, checksig(&der, 33), vec![]);
+            let res = exec.result().unwrap();
+            assert_eq!(res.error, Some(ExecError::CryptoUnavailable));
+        }
+    }
+
+    #[test]
+    fn op_limit_is_per_script() {
+        let nops = |n| {
+            let mut builder = Builder::new();
+            for _ in 0..n {
+                builder = builder.push_opcode(OP_NOP);
+            }
+            builder.push_opcode(OP_PUSHNUM_1).into_script()
+        };
+
+        // Like in Core, the scriptSig and the scriptPubKey each get 201 opcodes.
+        let script_sig = run(ExecCtx::Legacy, Options::default(), nops(150), vec![]);
+        let stack = script_sig
+            .result()
+            .unwrap()
+            .final_stack
+            .iter_str()
+            .collect();
+        let script_pubkey = run(ExecCtx::Legacy, Options::default(), nops(150), stack);
+        assert!(script_pubkey.result().unwrap().success);
+        assert_eq!(script_pubkey.op_count(), 150);
+
+        let exec = run(ExecCtx::Legacy, Options::default(), nops(201), vec![]);
+        assert!(exec.result().unwrap().success);
+        let exec = run(ExecCtx::Legacy, Options::default(), nops(202), vec![]);
+        assert_eq!(exec.result().unwrap().error, Some(ExecError::OpCount));
+    }
+
+    #[test]
+    fn result_taint_follows_dependencies() {
+        let opt = Options {
+            track_taint: true,
+            ..Default::default()
+        };
+        // Drop the top witness element and check that the other two sum to 8.
+        let script = Builder::new()
+            .push_opcode(OP_DROP)
+            .push_opcode(OP_ADD)
+            .push_int(8)
+            .push_opcode(OP_EQUAL)
+            .into_script();
+        let witness = vec![vec![5], vec![3], vec![9]];
+        let exec = run(ExecCtx::Legacy, opt.clone(), script, witness);
+        assert!(exec.result().unwrap().success);
+        assert_eq!(exec.result_taint(), Some(Taint::from([0, 1])));
+        assert_eq!(exec.stack_taint().unwrap().len(), 1);
+
+        // A constant pushed in a branch depends on the branch condition.
+        let script = Builder::new()
+            .push_opcode(OP_IF)
+            .push_int(1)
+            .push_opcode(OP_ELSE)
+            .push_int(0)
+            .push_opcode(OP_ENDIF)
+            .into_script();
+        let exec = run(ExecCtx::Legacy, opt, script, vec![vec![7], vec![1]]);
+        assert_eq!(exec.result_taint(), Some(Taint::from([1])));
+        assert_eq!(
+            exec.stack_taint(),
+            Some(&[Taint::from([0]), Taint::from([1])][..])
+        );
+
+        let exec = run(
+            ExecCtx::Legacy,
+            Options::default(),
+            ScriptBuf::new(),
+            vec![],
+        );
+        assert_eq!(exec.result_taint(), None);
+    }
+
+    #[test]
+    fn hashing_stops_at_the_cap() {
+        let mut builder = Builder::new();
+        for _ in 0..10 {
+            builder = builder
+                .push_opcode(OP_DUP)
+                .push_opcode(OP_SHA256)
+                .push_opcode(OP_DROP);
+        }
+        let script = builder.into_script();
+        let witness = vec![vec![1; 100]];
+
+        let exec = run(
+            ExecCtx::Legacy,
+            Options::default(),
+            script.clone(),
+            witness.clone(),
+        );
+        assert!(exec.result().unwrap().success);
+        assert_eq!(exec.stats().hashed_bytes, 1000);
+
+        let mut opt = Options::default();
+        opt.limits.max_hash_bytes = Some(500);
+        let exec = run(ExecCtx::Legacy, opt, script, witness);
+        let res = exec.result().unwrap();
+        assert_eq!(res.error, Some(ExecError::ResourceExhausted));
+        assert_eq!(res.opcode, Some(OP_SHA256));
+        // The sixth hash would go over the cap, it is not reported.
+        assert_eq!(exec.stats().hashed_bytes, 500);
+    }
+
+    #[test]
+    fn strict_sighash_rejects_undefined_types() {
+        let script = |hashtype| {
+            Builder::new()
+                .push_slice([0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x02, hashtype])
+                .push_slice([2; 33])
+                .push_opcode(OP_CHECKSIG)
+                .into_script()
+        };
+        let strict = Options {
+            strict_sighash: true,
+            ..Default::default()
+        };
+        for hashtype in [0x00, 0x04] {
+            let exec = run(ExecCtx::Legacy, strict.clone(), script(hashtype), vec![]);
+            let res = exec.result().unwrap();
+            assert_eq!(res.error, Some(ExecError::SigHashType), "{:#x}", hashtype);
+
+            // Consensus only fails the signature check.
+            #[cfg(feature = "verify")]
+            {
+                let exec = run(
+                    ExecCtx::Legacy,
+                    Options::default(),
+                    script(hashtype),
+                    vec![],
+                );
+                assert_eq!(exec.result().unwrap().error, None, "{:#x}", hashtype);
+            }
+        }
+        #[cfg(feature = "verify")]
+        {
+            let exec = run(ExecCtx::Legacy, strict, script(0x81), vec![]);
+            assert_eq!(exec.result().unwrap().error, None);
+        }
+    }
+
+    #[test]
+    fn from_instructions_runs_the_sequence() {
+        let data = <&script::PushBytes>::try_from(&[2, 3][..]).unwrap();
+        let instructions = vec![
+            Instruction::PushBytes(data),
+            Instruction::Op(OP_SIZE),
+            Instruction::Op(OP_PUSHNUM_2),
+            Instruction::Op(OP_EQUALVERIFY),
+        ];
+        let tx = TxTemplate::dummy();
+        let mut exec = Exec::from_instructions(
+            ExecCtx::Legacy,
+            Options::default(),
+            tx,
+            instructions,
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(exec.script().as_bytes(), [0x02, 2, 3, 0x82, 0x52, 0x88]);
+        while exec.exec_next().is_ok() {}
+        let res = exec.result().unwrap();
+        assert!(res.success);
+        assert_eq!(res.final_stack.iter_str().collect::<Vec<_>>(), [vec![2, 3]]);
+
+        // Pushes are re-encoded with the shortest prefix.
+        let parsed = ScriptBuf::from(vec![0x4c, 0x01, 0x05]);
+        let tx = TxTemplate::dummy();
+        let exec = Exec::from_instructions(
+            ExecCtx::Legacy,
+            Options::default(),
+            tx,
+            parsed.instructions().map(Result::unwrap),
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(exec.script().as_bytes(), [0x01, 0x05]);
+    }
+
+    #[test]
+    fn executions_run_on_several_threads() {
+        let script = Builder::new()
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_ADD)
+            .push_int(8)
+            .push_opcode(OP_LESSTHAN)
+            .into_script();
+        std::thread::scope(|s| {
+            let threads: Vec<_> = (1..=8)
+                .map(|i| {
+                    let script = &script;
+                    s.spawn(move || {
+                        let exec = run(
+                            ExecCtx::Legacy,
+                            Options::default(),
+                            script.clone(),
+                            vec![vec![i]],
+                        );
+                        exec.result().unwrap().success
+                    })
+                })
+                .collect();
+            for (i, thread) in (1..=8).zip(threads) {
+                assert_eq!(thread.join().unwrap(), 2 * i < 8, "{}", i);
+            }
+        });
+    }
+
+    #[test]
+    fn checkmultisig_counts_are_4_byte_numbers() {
+        let opt = Options {
+            max_num_size: 8,
+            ..Default::default()
+        };
+        let script = Builder::new().push_opcode(OP_CHECKMULTISIG).into_script();
+        let witness = vec![utils::scriptint_vec(1 << 32)];
+        let exec = run(ExecCtx::Legacy, opt, script, witness);
+        let res = exec.result().unwrap();
+        assert_eq!(res.error, Some(ExecError::ScriptIntNumericOverflow));
+
+        // Every key counts towards the opcode limit.
+        let mut builder = Builder::new().push_int(0).push_int(0);
+        for _ in 0..3 {
+            builder = builder.push_slice([2; 33]);
+        }
+        let script = builder
+            .push_int(3)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        let exec = run(ExecCtx::Legacy, Options::default(), script, vec![]);
+        assert_eq!(exec.result().unwrap().error, None);
+        assert_eq!(exec.op_count(), 4);
+    }
+
+    #[test]
+    fn minimal_numbers_per_category() {
+        let scripts = [
+            Builder::new().push_opcode(OP_1ADD),
+            Builder::new().push_int(1).push_opcode(OP_NUMEQUAL),
+            Builder::new().push_opcode(OP_CLTV),
+        ];
+        let toggles: [fn(&mut Options); 3] = [
+            |opt| opt.minimal_arithmetic = false,
+            |opt| opt.minimal_comparison = false,
+            |opt| opt.minimal_locktime = false,
+        ];
+        let lock_time = bitcoin::locktime::absolute::LockTime::from_height(1).unwrap();
+        let tx = TxTemplate::with_timelocks(transaction::Version::TWO, lock_time, Sequence::ZERO);
+        // 1 with a needless zero byte.
+        let witness = vec![vec![1, 0]];
+
+        for (i, toggle) in toggles.iter().enumerate() {
+            let mut opt = Options::default();
+            toggle(&mut opt);
+            for (j, script) in scripts.iter().enumerate() {
+                let script = script.clone().into_script();
+                let mut exec = Exec::new(
+                    ExecCtx::Legacy,
+                    opt.clone(),
+                    tx.clone(),
+                    script,
+                    witness.clone(),
+                )
+                .unwrap();
+                while exec.exec_next().is_ok() {}
+                let expected = if i == j {
+                    None
+                } else {
+                    Some(ExecError::MinimalData)
+                };
+                assert_eq!(exec.result().unwrap().error, expected, "{} {}", i, j);
+            }
+        }
+    }
+
+    #[test]
+    fn numequal_results_read_as_canonical_booleans() {
+        for (op, equal) in [(OP_NUMEQUAL, true), (OP_NUMNOTEQUAL, false)] {
+            for (a, b) in [(3, 3), (3, 4)] {
+                // The result is 0x01 if true, the empty vector if false.
+                let len = ((a == b) == equal) as i64;
+                // Both numbers, both byte strings, and one of each.
+                let operands = [
+                    (Builder::new().push_int(a).push_int(b), vec![]),
+                    (Builder::new(), vec![vec![a as u8], vec![b as u8]]),
+                    (Builder::new().push_int(b), vec![vec![a as u8]]),
+                ];
+                for (builder, witness) in operands {
+                    // OP_SIZE and OP_EQUAL observe the encoding of the result,
+                    // OP_0 and OP_1 push the empty vector and 0x01.
+                    let script = builder
+                        .push_opcode(op)
+                        .push_opcode(OP_DUP)
+                        .push_opcode(OP_SIZE)
+                        .push_int(len)
+                        .push_opcode(OP_EQUALVERIFY)
+                        .push_int(len)
+                        .push_opcode(OP_EQUAL)
+                        .into_script();
+                    let exec = run(ExecCtx::Legacy, Options::default(), script, witness);
+                    let res = exec.result().unwrap();
+                    assert!(res.success, "{} {} {}", op, a, b);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn garbage_pubkeys() {
+        let der = [0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x02, 0x01];
+        let checksig = |sig: &[u8], pk: &[u8]| {
+            Builder::new()
+                .push_slice(<&script::PushBytes>::try_from(sig).unwrap())
+                .push_slice(<&script::PushBytes>::try_from(pk).unwrap())
+                .push_opcode(OP_CHECKSIG)
+                .into_script()
+        };
+
+        // A key that doesn't parse fails the check in legacy scripts.
+        for pk in [&[0x05; 33][..], &[0x02; 33], &[0x04; 20]] {
+            let exec = run(
+                ExecCtx::Legacy,
+                Options::default(),
+                checksig(&der, pk),
+                vec![],
+            );
+            let res = exec.result().unwrap();
+            assert_eq!(res.error, None);
+            assert!(res.final_stack.last().unwrap().is_empty());
+
+            let opt = Options {
+                strict_pubkey: true,
+                ..Default::default()
+            };
+            let exec = run(ExecCtx::Legacy, opt, checksig(&der, pk), vec![]);
+            let expected = if pk[0] == 0x02 {
+                None
+            } else {
+                Some(ExecError::PubkeyType)
+            };
+            assert_eq!(exec.result().unwrap().error, expected);
+        }
+
+        // A 32-byte key that isn't a point fails the script in Tapscript.
+        let script = checksig(&[1; 64], &[0xff; 32]);
+        let exec = run(ExecCtx::Tapscript, Options::default(), script, vec![]);
+        assert_eq!(exec.result().unwrap().error, Some(ExecError::SchnorrSig));
+    }
+
+    #[test]
+    fn tapscript_pubkey_lengths() {
+        let checksig = |sig: &[u8], pk: &[u8]| {
+            let script = Builder::new().push_opcode(OP_CHECKSIG).into_script();
+            let witness = vec![sig.to_vec(), pk.to_vec()];
+            let exec = run(ExecCtx::Tapscript, Options::default(), script, witness);
+            let res = exec.result().unwrap();
+            (res.error.clone(), res.success)
+        };
+
+        // An empty key fails the script, even with an empty signature.
+        assert_eq!(
+            checksig(&[1; 64], &[]),
+            (Some(ExecError::PubkeyType), false)
+        );
+        assert_eq!(checksig(&[], &[]), (Some(ExecError::PubkeyType), false));
+        // Keys of unknown types succeed for any non-empty signature.
+        for len in [1, 20, 31, 33, 65] {
+            assert_eq!(checksig(&[1], &vec![2; len]), (None, true), "{}", len);
+            assert_eq!(checksig(&[], &vec![2; len]), (None, false), "{}", len);
+        }
+        // 32-byte keys are verified.
+        assert_eq!(checksig(&[], &[2; 32]), (None, false));
+        #[cfg(feature = "verify")]
+        assert_eq!(
+            checksig(&[1; 64], &[0xff; 32]),
+            (Some(ExecError::SchnorrSig), false)
+        );
+    }
+
+    #[test]
+    fn small_validation_budget() {
+        // Two signature checks against keys of an unknown type.
+        let script = Builder::new()
+            .push_slice([2; 33])
+            .push_opcode(OP_CHECKSIGVERIFY)
+            .push_slice([2; 33])
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let witness = vec![vec![1; 64], vec![1; 64]];
+        for (witness_size, error) in [
+            (0, Some(ExecError::TapscriptValidationWeight)),
+            (49, Some(ExecError::TapscriptValidationWeight)),
+            (50, None),
+        ] {
+            let opt = Options {
+                witness_size: Some(witness_size),
+                ..Default::default()
+            };
+            let exec = run(ExecCtx::Tapscript, opt, script.clone(), witness.clone());
+            assert_eq!(exec.result().unwrap().error, error, "{}", witness_size);
+        }
+
+        // Empty signatures don't use the budget.
+        let opt = Options {
+            witness_size: Some(0),
+            ..Default::default()
+        };
+        let witness = vec![vec![1; 64], vec![]];
+        let script = Builder::new()
+            .push_slice([2; 33])
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_NOTIF)
+            .push_slice([2; 33])
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_ENDIF)
+            .into_script();
+        let exec = run(ExecCtx::Tapscript, opt, script, witness);
+        assert!(exec.result().unwrap().success);
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn bad_signature_aborts_checksigverify() {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        // A valid signature of another message.
+        let msg = secp256k1::Message::from_digest([1; 32]);
+        let mut sig = secp.sign_ecdsa(&msg, &sk).serialize_der().to_vec();
+        sig.push(0x01);
+        let script = Builder::new()
+            .push_slice(script::PushBytesBuf::try_from(sig).unwrap())
+            .push_slice(sk.public_key(&secp).serialize())
+            .push_opcode(OP_CHECKSIGVERIFY)
+            .push_opcode(OP_PUSHNUM_1)
+            .into_script();
+
+        let exec = run(ExecCtx::Legacy, Options::default(), script.clone(), vec![]);
+        let res = exec.result().unwrap();
+        assert_eq!(res.error, Some(ExecError::CheckSigVerify));
+        assert_eq!(res.opcode, Some(OP_CHECKSIGVERIFY));
+        assert!(!res.success);
+        // The script stops at the failing check.
+        assert_eq!(res.final_stack.iter_str().collect::<Vec<_>>(), [vec![]]);
+
+        let opt = Options {
+            null_fail: true,
+            ..Options::default()
+        };
+        let exec = run(ExecCtx::Legacy, opt, script, vec![]);
+        assert_eq!(exec.result().unwrap().error, Some(ExecError::NullFail));
+    }
+
+    #[test]
+    fn csv_version_gate() {
+        let csv = |n: i64, version, sequence| {
+            let script = Builder::new().push_int(n).push_opcode(OP_CSV).into_script();
+            let tx = TxTemplate::with_timelocks(
+                transaction::Version(version),
+                bitcoin::locktime::absolute::LockTime::ZERO,
+                Sequence(sequence),
+            );
+            let mut exec =
+                Exec::new(ExecCtx::Legacy, Options::default(), tx, script, vec![]).unwrap();
+            while exec.exec_next().is_ok() {}
+            exec.result().unwrap().error.clone()
+        };
+        let unsatisfied = Some(ExecError::UnsatisfiedLocktime);
+
+        assert_eq!(csv(10, 2, 10), None);
+        assert_eq!(csv(10, 2, 9), unsatisfied);
+        // BIP 112 only applies to version 2 transactions and later.
+        assert_eq!(csv(10, 1, 10), unsatisfied);
+        assert_eq!(csv(10, 3, 10), None);
+        // With the disable flag of the input set, nothing is satisfied.
+        assert_eq!(csv(10, 2, 10 | 1 << 31), unsatisfied);
+        // With the disable flag of the operand set, OP_CSV is a NOP.
+        assert_eq!(csv(1 << 31, 1, 0), None);
+        assert_eq!(csv(-1, 2, 10), Some(ExecError::NegativeLocktime));
+    }
+
+    #[test]
+    fn custom_cost_model() {
+        // 1 + 2 * (1 + 10) + 1 without the unexecuted branch, whose
+        // conditionals are charged but not its OP_SHA256.
+        let script = Builder::new()
+            .push_slice([7; 32])
+            .push_opcode(OP_SHA256)
+            .push_opcode(OP_SHA256)
+            .push_opcode(OP_PUSHBYTES_0)
+            .push_opcode(OP_IF)
+            .push_opcode(OP_SHA256)
+            .push_opcode(OP_ENDIF)
+            .into_script();
+        let opt = Options {
+            cost_model: CostModel::default().with_cost(OP_SHA256, 10),
+            ..Default::default()
+        };
+        let exec = run(ExecCtx::Legacy, opt, script.clone(), vec![]);
+        assert!(exec.result().unwrap().success);
+        assert_eq!(exec.total_cost(), 1 + 10 + 10 + 1 + 1 + 1);
+
+        let exec = run(ExecCtx::Legacy, Options::default(), script, vec![]);
+        assert_eq!(exec.total_cost(), 6);
+        assert_eq!(CostModel::new(3).cost(OP_SHA256), 3);
+    }
+
+    #[test]
+    fn repeated_else_alternates() {
+        let script = |cond: i64| {
+            Builder::new()
+                .push_int(cond)
+                .push_opcode(OP_IF)
+                .push_int(2)
+                .push_opcode(OP_ELSE)
+                .push_int(3)
+                .push_opcode(OP_ELSE)
+                .push_int(4)
+                .push_opcode(OP_ENDIF)
+                .into_script()
+        };
+        let stack = |exec: &Exec| exec.stack().iter_str().collect::<Vec<_>>();
+
+        let exec = run(ExecCtx::Legacy, Options::default(), script(1), vec![]);
+        assert_eq!(exec.result().unwrap().error, None);
+        assert_eq!(stack(&exec), vec![vec![2], vec![4]]);
+
+        let exec = run(ExecCtx::Legacy, Options::default(), script(0), vec![]);
+        assert_eq!(exec.result().unwrap().error, None);
+        assert_eq!(stack(&exec), vec![vec![3]]);
+    }
+
+    #[test]
+    fn state_hash_tracks_the_execution() {
+        let script = Builder::new()
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_ADD)
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_TOALTSTACK)
+            .into_script();
+        let new = |item: Vec<u8>| {
+            Exec::new(
+                ExecCtx::Tapscript,
+                Options::default(),
+                TxTemplate::dummy(),
+                script.clone(),
+                vec![item],
+            )
+            .unwrap()
+        };
+
+        let mut a = new(vec![1]);
+        let mut b = new(vec![1]);
+        let mut c = new(vec![2]);
+        let mut seen = Vec::new();
+        loop {
+            assert_eq!(a.state_hash(), b.state_hash());
+            assert_ne!(a.state_hash(), c.state_hash());
+            assert!(!seen.contains(&a.state_hash()));
+            seen.push(a.state_hash());
+            let done = a.exec_next().is_err();
+            assert_eq!(b.exec_next().is_err(), done);
+            assert_eq!(c.exec_next().is_err(), done);
+            if done {
+                break;
+            }
+        }
+        assert_eq!(a.state_hash(), b.state_hash());
+        assert_ne!(a.state_hash(), c.state_hash());
+    }
+}
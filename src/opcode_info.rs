@@ -0,0 +1,225 @@
+use bitcoin::opcodes::{all::*, Opcode};
+
+/// The group an opcode belongs to, following the grouping in Bitcoin Core's script.h.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpcodeCategory {
+    /// Data and number pushes.
+    Push,
+    /// Conditionals, verification and OP_RETURN.
+    Flow,
+    /// Stack manipulation.
+    Stack,
+    /// Operations on byte strings.
+    Splice,
+    /// Bit logic and equality.
+    Bitwise,
+    /// Numeric operations.
+    Arithmetic,
+    /// Hashes and signature checks.
+    Crypto,
+    /// Timelocks and the NOPs reserved for upgrades.
+    Expansion,
+}
+
+/// Metadata describing an opcode executed by this crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    pub opcode: Opcode,
+    pub name: String,
+    pub category: OpcodeCategory,
+    pub description: &'static str,
+}
+
+/// The category and description of [op], or [None] if it can't be executed.
+fn describe(op: Opcode) -> Option<(OpcodeCategory, &'static str)> {
+    use OpcodeCategory::*;
+    if op.to_u8() >= OP_PUSHBYTES_1.to_u8() && op.to_u8() <= OP_PUSHBYTES_75.to_u8() {
+        return Some((Push, "Push the next n bytes, for n from 1 to 75."));
+    }
+    if op.to_u8() >= OP_PUSHNUM_1.to_u8() && op.to_u8() <= OP_PUSHNUM_16.to_u8() {
+        return Some((Push, "Push the number n, for n from 1 to 16."));
+    }
+    Some(match op {
+        OP_PUSHBYTES_0 => (Push, "Push an empty byte string."),
+        OP_PUSHDATA1 => (Push, "Push the number of bytes given by the next byte."),
+        OP_PUSHDATA2 => (Push, "Push the number of bytes given by the next 2 bytes."),
+        OP_PUSHDATA4 => (Push, "Push the number of bytes given by the next 4 bytes."),
+        OP_PUSHNUM_NEG1 => (Push, "Push the number -1."),
+        OP_NOP => (Flow, "Do nothing."),
+        OP_IF => (
+            Flow,
+            "Execute the following branch if the top element is true.",
+        ),
+        OP_NOTIF => (
+            Flow,
+            "Execute the following branch if the top element is false.",
+        ),
+        OP_ELSE => (Flow, "Toggle execution of the current branch."),
+        OP_ENDIF => (Flow, "End the current conditional."),
+        OP_VERIFY => (Flow, "Fail unless the top element is true, then pop it."),
+        OP_RETURN => (Flow, "Fail immediately."),
+        OP_TOALTSTACK => (Stack, "Move the top element to the altstack."),
+        OP_FROMALTSTACK => (Stack, "Move the top element of the altstack to the stack."),
+        OP_2DROP => (Stack, "Pop the top two elements."),
+        OP_2DUP => (Stack, "Duplicate the top two elements."),
+        OP_3DUP => (Stack, "Duplicate the top three elements."),
+        OP_2OVER => (Stack, "Copy the third and fourth elements to the top."),
+        OP_2ROT => (Stack, "Move the fifth and sixth elements to the top."),
+        OP_2SWAP => (Stack, "Swap the top two pairs of elements."),
+        OP_IFDUP => (Stack, "Duplicate the top element if it is true."),
+        OP_DEPTH => (Stack, "Push the number of stack elements."),
+        OP_DROP => (Stack, "Pop the top element."),
+        OP_DUP => (Stack, "Duplicate the top element."),
+        OP_NIP => (Stack, "Remove the second element."),
+        OP_OVER => (Stack, "Copy the second element to the top."),
+        OP_PICK => (Stack, "Copy the element n back to the top."),
+        OP_ROLL => (Stack, "Move the element n back to the top."),
+        OP_ROT => (Stack, "Move the third element to the top."),
+        OP_SWAP => (Stack, "Swap the top two elements."),
+        OP_TUCK => (Stack, "Copy the top element below the second element."),
+        OP_CAT => (
+            Splice,
+            "Concatenate the top two elements (experimental, Tapscript only).",
+        ),
+        OP_SIZE => (Splice, "Push the size of the top element."),
+        OP_EQUAL => (Bitwise, "Push whether the top two elements are equal."),
+        OP_EQUALVERIFY => (Bitwise, "OP_EQUAL followed by OP_VERIFY."),
+        OP_1ADD => (Arithmetic, "Add 1 to the top number."),
+        OP_1SUB => (Arithmetic, "Subtract 1 from the top number."),
+        OP_NEGATE => (Arithmetic, "Negate the top number."),
+        OP_ABS => (Arithmetic, "Replace the top number by its absolute value."),
+        OP_NOT => (Arithmetic, "Push 1 if the top number is 0, 0 otherwise."),
+        OP_0NOTEQUAL => (Arithmetic, "Push 0 if the top number is 0, 1 otherwise."),
+        OP_ADD => (Arithmetic, "Add the top two numbers."),
+        OP_SUB => (Arithmetic, "Subtract the top number from the second."),
+        OP_MUL => (
+            Arithmetic,
+            "Multiply the top two numbers (experimental, Tapscript only).",
+        ),
+        OP_DIV => (
+            Arithmetic,
+            "Divide the second number by the top (experimental, Tapscript only).",
+        ),
+        OP_BOOLAND => (Arithmetic, "Push whether both top numbers are non-zero."),
+        OP_BOOLOR => (Arithmetic, "Push whether either top number is non-zero."),
+        OP_NUMEQUAL => (Arithmetic, "Push whether the top two numbers are equal."),
+        OP_NUMEQUALVERIFY => (Arithmetic, "OP_NUMEQUAL followed by OP_VERIFY."),
+        OP_NUMNOTEQUAL => (Arithmetic, "Push whether the top two numbers differ."),
+        OP_LESSTHAN => (
+            Arithmetic,
+            "Push whether the second number is less than the top.",
+        ),
+        OP_GREATERTHAN => (
+            Arithmetic,
+            "Push whether the second number is greater than the top.",
+        ),
+        OP_LESSTHANOREQUAL => (
+            Arithmetic,
+            "Push whether the second number is at most the top.",
+        ),
+        OP_GREATERTHANOREQUAL => (
+            Arithmetic,
+            "Push whether the second number is at least the top.",
+        ),
+        OP_MIN => (Arithmetic, "Push the smaller of the top two numbers."),
+        OP_MAX => (Arithmetic, "Push the larger of the top two numbers."),
+        OP_WITHIN => (
+            Arithmetic,
+            "Push whether a number is within a half-open range.",
+        ),
+        OP_RIPEMD160 => (Crypto, "Hash the top element with RIPEMD-160."),
+        OP_SHA1 => (Crypto, "Hash the top element with SHA-1."),
+        OP_SHA256 => (Crypto, "Hash the top element with SHA-256."),
+        OP_HASH160 => (Crypto, "Hash the top element with SHA-256 then RIPEMD-160."),
+        OP_HASH256 => (Crypto, "Hash the top element with SHA-256 twice."),
+        OP_CODESEPARATOR => (Crypto, "Mark the start of the signed script code."),
+        OP_CHECKSIG => (
+            Crypto,
+            "Push whether a signature is valid for a public key.",
+        ),
+        OP_CHECKSIGVERIFY => (Crypto, "OP_CHECKSIG followed by OP_VERIFY."),
+        OP_CHECKMULTISIG => (
+            Crypto,
+            "Push whether m signatures match m of n public keys (not in Tapscript).",
+        ),
+        OP_CHECKMULTISIGVERIFY => (
+            Crypto,
+            "OP_CHECKMULTISIG followed by OP_VERIFY (not in Tapscript).",
+        ),
+        OP_CHECKSIGADD => (
+            Crypto,
+            "Add 1 to a counter if a signature is valid (Tapscript only).",
+        ),
+        OP_NOP1 => (Expansion, "Do nothing."),
+        OP_CLTV => (
+            Expansion,
+            "Fail unless the transaction locktime has passed the top number.",
+        ),
+        OP_CSV => (
+            Expansion,
+            "Fail unless the input's relative locktime has passed the top number.",
+        ),
+        OP_NOP4 => (Expansion, "Do nothing."),
+        OP_NOP5 => (Expansion, "Do nothing."),
+        OP_NOP6 => (Expansion, "Do nothing."),
+        OP_NOP7 => (Expansion, "Do nothing."),
+        OP_NOP8 => (Expansion, "Do nothing."),
+        OP_NOP9 => (Expansion, "Do nothing."),
+        OP_NOP10 => (Expansion, "Do nothing."),
+        _ => return None,
+    })
+}
+
+/// Lists every opcode this crate can execute, with a short description.
+///
+/// Pushes and numbers are listed once per opcode, so OP_PUSHBYTES_1 to
+/// OP_PUSHBYTES_75 and OP_1 to OP_16 all have their own entry. Disabled and
+/// reserved opcodes are not listed, experimental ones are.
+pub fn supported_opcodes() -> Vec<OpcodeInfo> {
+    (0..=u8::MAX)
+        .map(Opcode::from)
+        .filter_map(|opcode| {
+            describe(opcode).map(|(category, description)| OpcodeInfo {
+                opcode,
+                name: opcode.to_string(),
+                category,
+                description,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supported_opcodes_have_their_category() {
+        use OpcodeCategory::*;
+
+        let opcodes = supported_opcodes();
+        let category = |op: Opcode| {
+            opcodes
+                .iter()
+                .find(|info| info.opcode == op)
+                .map(|info| info.category)
+        };
+        assert_eq!(category(OP_PUSHBYTES_0), Some(Push));
+        assert_eq!(category(OP_PUSHBYTES_75), Some(Push));
+        assert_eq!(category(OP_PUSHNUM_16), Some(Push));
+        assert_eq!(category(OP_IF), Some(Flow));
+        assert_eq!(category(OP_DUP), Some(Stack));
+        assert_eq!(category(OP_CAT), Some(Splice));
+        assert_eq!(category(OP_EQUALVERIFY), Some(Bitwise));
+        assert_eq!(category(OP_ADD), Some(Arithmetic));
+        assert_eq!(category(OP_CHECKSIGADD), Some(Crypto));
+        assert_eq!(category(OP_CSV), Some(Expansion));
+        // Reserved and disabled opcodes aren't listed.
+        assert_eq!(category(OP_RESERVED), None);
+        assert_eq!(category(OP_SUBSTR), None);
+
+        let dup = opcodes.iter().find(|info| info.opcode == OP_DUP).unwrap();
+        assert_eq!(dup.name, "OP_DUP");
+        assert!(opcodes.iter().all(|info| !info.description.is_empty()));
+    }
+}
@@ -1,83 +1,171 @@
-use bitcoin::secp256k1::{self, PublicKey, XOnlyPublicKey};
-use bitcoin::sighash::{Annex, EcdsaSighashType, Prevouts, TapSighashType};
+#[cfg(feature = "verify")]
+use bitcoin::secp256k1::{PublicKey, XOnlyPublicKey};
+#[cfg(feature = "verify")]
+use bitcoin::sighash::{Annex, Prevouts};
+use bitcoin::sighash::{EcdsaSighashType, TapSighashType};
+#[cfg(feature = "verify")]
+use bitcoin::taproot::ControlBlock;
 
 use crate::*;
 
+#[cfg(feature = "verify")]
 lazy_static::lazy_static! {
     static ref SECP: secp256k1::Secp256k1<secp256k1::All> = secp256k1::Secp256k1::new();
 }
 
+/// Parses the sighash type byte of an ECDSA signature.
+///
+/// Consensus accepts any byte here, undefined types are hashed like
+/// SIGHASH_ALL. With [strict], only ALL, NONE and SINGLE, optionally
+/// combined with ANYONECANPAY, are accepted, like Bitcoin Core's
+/// SCRIPT_VERIFY_STRICTENC policy.
+pub fn parse_ecdsa_sighash_type(byte: u8, strict: bool) -> Result<EcdsaSighashType, ExecError> {
+    if strict {
+        EcdsaSighashType::from_standard(byte as u32).map_err(|_| ExecError::SigHashType)
+    } else {
+        Ok(EcdsaSighashType::from_consensus(byte as u32))
+    }
+}
+
+/// Parses the sighash type byte of a 65-byte Schnorr signature.
+///
+/// 64-byte signatures implicitly use [TapSighashType::Default], which is
+/// not allowed to be explicitly set.
+pub fn parse_taproot_sighash_type(byte: u8) -> Result<TapSighashType, ExecError> {
+    if byte == TapSighashType::Default as u8 {
+        return Err(ExecError::SchnorrSigHashtype);
+    }
+    TapSighashType::from_consensus_u8(byte).map_err(|_| ExecError::SchnorrSigHashtype)
+}
+
+/// A signature decoded from its script representation.
+#[cfg(feature = "verify")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParsedSig {
+    Ecdsa(bitcoin::ecdsa::Signature),
+    Schnorr(bitcoin::taproot::Signature),
+}
+
+/// Decodes a DER-encoded ECDSA signature followed by its sighash type byte.
+///
+/// This does the same parsing as CHECKSIG, so undefined sighash types are
+/// accepted and hashed like SIGHASH_ALL.
+#[cfg(feature = "verify")]
+pub fn parse_ecdsa_sig(bytes: &[u8]) -> Result<ParsedSig, ExecError> {
+    let (hashtype, der) = bytes.split_last().ok_or(ExecError::SigDer)?;
+    let signature = secp256k1::ecdsa::Signature::from_der(der).map_err(|_| ExecError::SigDer)?;
+    Ok(ParsedSig::Ecdsa(bitcoin::ecdsa::Signature {
+        signature,
+        sighash_type: parse_ecdsa_sighash_type(*hashtype, false)?,
+    }))
+}
+
+/// Decodes a 64-byte Schnorr signature or a 65-byte one with a sighash type byte.
+#[cfg(feature = "verify")]
+pub fn parse_schnorr_sig(bytes: &[u8]) -> Result<ParsedSig, ExecError> {
+    let (sig, sighash_type) = match bytes.len() {
+        64 => (bytes, TapSighashType::Default),
+        65 => (&bytes[..64], parse_taproot_sighash_type(bytes[64])?),
+        _ => return Err(ExecError::SchnorrSigSize),
+    };
+    let signature =
+        secp256k1::schnorr::Signature::from_slice(sig).map_err(|_| ExecError::SchnorrSig)?;
+    Ok(ParsedSig::Schnorr(bitcoin::taproot::Signature {
+        signature,
+        sighash_type,
+    }))
+}
+
+/// Returns whether [control_block] proves that [script] is committed to by the
+/// 32-byte x-only [output_key], as required for BIP 341 script path spends.
+///
+/// The Merkle root is computed from the leaf hash of [script] and the path in
+/// the control block, and the internal key tweaked with it must be the output
+/// key with the parity given in the control block. Control blocks that aren't
+/// 33 + 32k bytes long, with at most 128 path elements, are rejected.
+#[cfg(feature = "verify")]
+pub fn verify_taproot_commitment(output_key: &[u8], control_block: &[u8], script: &Script) -> bool {
+    let Ok(output_key) = XOnlyPublicKey::from_slice(output_key) else {
+        return false;
+    };
+    match ControlBlock::decode(control_block) {
+        Ok(cb) => cb.verify_taproot_commitment(&SECP, output_key, script),
+        Err(_) => false,
+    }
+}
+
+#[cfg(feature = "verify")]
 impl Exec {
-    pub fn check_sig_ecdsa(&mut self, sig: &[u8], pk: &[u8], script_code: &[u8]) -> bool {
+    /// Returns whether [sig] is a valid signature for [pk].
+    ///
+    /// Fails with [ExecError::InvalidSighash] if the transaction doesn't allow
+    /// computing the sighash, e.g. when the input index is out of range.
+    pub fn check_sig_ecdsa(
+        &mut self,
+        sig: &[u8],
+        pk: &[u8],
+        script_code: &[u8],
+    ) -> Result<bool, ExecError> {
         let pk = match PublicKey::from_slice(pk) {
             Ok(pk) => pk,
-            Err(_) => return false,
+            Err(_) => return Ok(false),
         };
 
-        if sig.is_empty() {
-            return false;
-        }
-
-        let hashtype = *sig.last().unwrap();
-        let sig = match secp256k1::ecdsa::Signature::from_der(&sig[0..sig.len() - 1]) {
-            Ok(s) => s,
-            Err(_) => return false,
+        let (parsed, hashtype) = match parse_ecdsa_sig(sig) {
+            Ok(ParsedSig::Ecdsa(s)) => (s.signature, s.sighash_type),
+            _ => return Ok(false),
         };
+        // The legacy sighash commits to the raw byte, even for undefined types.
+        let hashtype_byte = *sig.last().unwrap();
 
         let sighash = if self.ctx == ExecCtx::SegwitV0 {
-            self.sighashcache
-                .p2wsh_signature_hash(
-                    self.tx.input_idx,
-                    Script::from_bytes(script_code),
-                    self.tx.prevouts[self.tx.input_idx].value,
-                    //TODO(stevenroose) this might not actually emulate consensus behavior
-                    EcdsaSighashType::from_consensus(hashtype as u32),
-                )
-                .expect("only happens on prevout index out of bounds")
-                .into()
+            let prevout = self
+                .tx
+                .prevouts
+                .get(self.tx.input_idx)
+                .ok_or(ExecError::InvalidSighash)?;
+            match self.sighashcache.p2wsh_signature_hash(
+                self.tx.input_idx,
+                Script::from_bytes(script_code),
+                prevout.value,
+                //TODO(stevenroose) this might not actually emulate consensus behavior
+                hashtype,
+            ) {
+                Ok(h) => h.into(),
+                // only happens on input index out of bounds
+                Err(_) => return Err(ExecError::InvalidSighash),
+            }
         } else if self.ctx == ExecCtx::Legacy {
-            self.sighashcache
-                .legacy_signature_hash(
-                    self.tx.input_idx,
-                    Script::from_bytes(script_code),
-                    hashtype as u32,
-                )
-                .expect("TODO(stevenroose) seems to only happen if prevout index out of bound")
-                .into()
+            // This emulates the SIGHASH_SINGLE bug: with no output at the input's
+            // index, the hash signed is 1 instead of the transaction digest.
+            match self.sighashcache.legacy_signature_hash(
+                self.tx.input_idx,
+                Script::from_bytes(script_code),
+                hashtype_byte as u32,
+            ) {
+                Ok(h) => h.into(),
+                // only happens on input index out of bounds
+                Err(_) => return Err(ExecError::InvalidSighash),
+            }
         } else {
             unreachable!();
         };
 
-        SECP.verify_ecdsa(&sighash, &sig, &pk).is_ok()
+        Ok(SECP.verify_ecdsa(&sighash, &parsed, &pk).is_ok())
     }
 
     /// [pk] should be passed as 32-bytes.
     pub fn check_sig_schnorr(&mut self, sig: &[u8], pk: &[u8]) -> Result<(), ExecError> {
         assert_eq!(pk.len(), 32);
 
-        if sig.len() != 64 && sig.len() != 65 {
-            return Err(ExecError::SchnorrSigSize);
-        }
-
-        let pk = XOnlyPublicKey::from_slice(pk).expect("TODO(stevenroose) what to do here?");
-        let (sig, hashtype) = if sig.len() == 65 {
-            let b = *sig.last().unwrap();
-            let sig = secp256k1::schnorr::Signature::from_slice(&sig[0..sig.len() - 1])
-                .map_err(|_| ExecError::SchnorrSig)?;
-
-            if b == TapSighashType::Default as u8 {
-                return Err(ExecError::SchnorrSigHashtype);
-            }
-            //TODO(stevenroose) core does not error here
-            let sht =
-                TapSighashType::from_consensus_u8(b).map_err(|_| ExecError::SchnorrSigHashtype)?;
-            (sig, sht)
-        } else {
-            let sig = secp256k1::schnorr::Signature::from_slice(sig)
-                .map_err(|_| ExecError::SchnorrSig)?;
-            (sig, TapSighashType::Default)
+        let (sig, hashtype) = match parse_schnorr_sig(sig)? {
+            ParsedSig::Schnorr(s) => (s.signature, s.sighash_type),
+            ParsedSig::Ecdsa(_) => unreachable!(),
         };
 
+        // Like in Core, a key that doesn't parse simply fails verification.
+        let pk = XOnlyPublicKey::from_slice(pk).map_err(|_| ExecError::SchnorrSig)?;
+
         let (leaf_hash, annex) = self.tx.taproot_annex_scriptleaf.as_ref().unwrap();
         let sighash = self
             .sighashcache
@@ -90,7 +178,13 @@ impl Exec {
                 Some((*leaf_hash, self.last_codeseparator_pos.unwrap_or(u32::MAX))),
                 hashtype,
             )
-            .expect("TODO(stevenroose) seems to only happen if prevout index out of bound");
+            // only happens on input index out of bounds or missing prevouts
+            .map_err(|_| ExecError::InvalidSighash)?;
+
+        if self.opt.defer_schnorr_verification {
+            self.deferred_schnorr.push((sig, sighash.into(), pk));
+            return Ok(());
+        }
 
         if SECP.verify_schnorr(&sig, &sighash.into(), &pk) != Ok(()) {
             return Err(ExecError::SchnorrSig);
@@ -98,4 +192,260 @@ impl Exec {
 
         Ok(())
     }
+
+    /// Verifies all Schnorr signatures whose verification was deferred.
+    ///
+    /// libsecp256k1 doesn't expose batch verification, so the signatures are
+    /// still verified one by one, but only once the script has otherwise run
+    /// to completion.
+    pub(crate) fn verify_deferred_schnorr(&mut self) -> Result<(), ExecError> {
+        for (sig, msg, pk) in self.deferred_schnorr.drain(..) {
+            if SECP.verify_schnorr(&sig, &msg, &pk) != Ok(()) {
+                return Err(ExecError::SchnorrSig);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Without the `verify` feature, all signature checks fail.
+#[cfg(not(feature = "verify"))]
+impl Exec {
+    pub fn check_sig_ecdsa(
+        &mut self,
+        _sig: &[u8],
+        _pk: &[u8],
+        _script_code: &[u8],
+    ) -> Result<bool, ExecError> {
+        Err(ExecError::CryptoUnavailable)
+    }
+
+    pub fn check_sig_schnorr(&mut self, _sig: &[u8], _pk: &[u8]) -> Result<(), ExecError> {
+        Err(ExecError::CryptoUnavailable)
+    }
+
+    /// Nothing can be deferred without the `verify` feature.
+    pub(crate) fn verify_deferred_schnorr(&mut self) -> Result<(), ExecError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sighash_type_bytes() {
+        use EcdsaSighashType::*;
+
+        for (byte, ty) in [
+            (0x01, All),
+            (0x02, None),
+            (0x03, Single),
+            (0x81, AllPlusAnyoneCanPay),
+            (0x82, NonePlusAnyoneCanPay),
+            (0x83, SinglePlusAnyoneCanPay),
+        ] {
+            assert_eq!(parse_ecdsa_sighash_type(byte, true), Ok(ty));
+            assert_eq!(parse_ecdsa_sighash_type(byte, false), Ok(ty));
+        }
+        for byte in [0x00, 0x04, 0x80, 0x84, 0xff] {
+            assert_eq!(
+                parse_ecdsa_sighash_type(byte, true),
+                Err(ExecError::SigHashType)
+            );
+            // Consensus hashes undefined types like SIGHASH_ALL.
+            assert_eq!(
+                parse_ecdsa_sighash_type(byte, false).map(|t| t.to_u32() & 0x1f),
+                Ok(1)
+            );
+        }
+
+        assert_eq!(parse_taproot_sighash_type(0x01), Ok(TapSighashType::All));
+        assert_eq!(
+            parse_taproot_sighash_type(0x83),
+            Ok(TapSighashType::SinglePlusAnyoneCanPay)
+        );
+        for byte in [0x00, 0x04, 0x80, 0x84] {
+            assert_eq!(
+                parse_taproot_sighash_type(byte),
+                Err(ExecError::SchnorrSigHashtype)
+            );
+        }
+    }
+
+    /// Runs `<sig> <pk> OP_CHECKSIG` and returns whether it succeeded.
+    #[cfg(feature = "verify")]
+    fn checksig(ctx: ExecCtx, tx: TxTemplate, sig: Vec<u8>, pk: PublicKey) -> bool {
+        let script = bitcoin::script::Builder::new()
+            .push_slice(bitcoin::script::PushBytesBuf::try_from(sig).unwrap())
+            .push_slice(pk.serialize())
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let mut exec = Exec::new(ctx, Options::default(), tx, script, vec![]).unwrap();
+        while exec.exec_next().is_ok() {}
+        exec.result().unwrap().success
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn sighash_single_bug() {
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = sk.public_key(&SECP);
+        // The dummy transaction has no outputs, so there is none at the input's index.
+        let tx = TxTemplate::dummy();
+        assert!(tx.tx.output.is_empty());
+
+        // The legacy sighash is then the number 1.
+        let mut one = [0; 32];
+        one[0] = 1;
+        let msg = secp256k1::Message::from_digest(one);
+        let mut sig = SECP.sign_ecdsa(&msg, &sk).serialize_der().to_vec();
+        sig.push(EcdsaSighashType::Single as u8);
+        assert!(checksig(ExecCtx::Legacy, tx.clone(), sig.clone(), pk));
+
+        // Segwit v0 doesn't have the bug.
+        assert!(!checksig(ExecCtx::SegwitV0, tx, sig, pk));
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn parse_signatures() {
+        // r = 1 and s = 2 with SIGHASH_SINGLE|ANYONECANPAY.
+        let der = [0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x02, 0x83];
+        let Ok(ParsedSig::Ecdsa(sig)) = parse_ecdsa_sig(&der) else {
+            panic!("invalid ECDSA signature");
+        };
+        assert_eq!(sig.sighash_type, EcdsaSighashType::SinglePlusAnyoneCanPay);
+        let compact = sig.signature.serialize_compact();
+        assert_eq!((compact[31], compact[63]), (1, 2));
+        assert_eq!(sig.signature.serialize_der().as_ref(), &der[..8]);
+        assert_eq!(parse_ecdsa_sig(&der[..8]), Err(ExecError::SigDer));
+        assert_eq!(parse_ecdsa_sig(&[]), Err(ExecError::SigDer));
+
+        let mut bytes = vec![7; 64];
+        let Ok(ParsedSig::Schnorr(sig)) = parse_schnorr_sig(&bytes) else {
+            panic!("invalid Schnorr signature");
+        };
+        assert_eq!(sig.sighash_type, TapSighashType::Default);
+        assert_eq!(sig.signature.as_ref(), &bytes[..]);
+        bytes.push(0x82);
+        let Ok(ParsedSig::Schnorr(sig)) = parse_schnorr_sig(&bytes) else {
+            panic!("invalid Schnorr signature");
+        };
+        assert_eq!(sig.sighash_type, TapSighashType::NonePlusAnyoneCanPay);
+        bytes.push(0x01);
+        assert_eq!(parse_schnorr_sig(&bytes), Err(ExecError::SchnorrSigSize));
+        assert_eq!(
+            parse_schnorr_sig(&bytes[..63]),
+            Err(ExecError::SchnorrSigSize)
+        );
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn uncomputable_sighash_fails() {
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = sk.public_key(&SECP);
+        let msg = secp256k1::Message::from_digest([1; 32]);
+        let sig = SECP.sign_ecdsa(&msg, &sk).serialize_der().to_vec();
+        let script = bitcoin::script::Builder::new()
+            .push_slice(pk.serialize())
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let run = |ctx, tx, hashtype| {
+            let mut sig = sig.clone();
+            sig.push(hashtype as u8);
+            let opt = Options::default();
+            let mut exec = Exec::new(ctx, opt, tx, script.clone(), vec![sig]).unwrap();
+            while exec.exec_next().is_ok() {}
+            exec.result().unwrap().clone()
+        };
+
+        // The spent input is not in the transaction.
+        for ctx in [ExecCtx::Legacy, ExecCtx::SegwitV0] {
+            let mut tx = TxTemplate::dummy();
+            tx.input_idx = 3;
+            tx.prevouts = vec![TxOut::NULL; 4];
+            let res = run(ctx, tx, EcdsaSighashType::All);
+            assert_eq!(res.error, Some(ExecError::InvalidSighash), "{:?}", ctx);
+        }
+
+        // SIGHASH_SINGLE without a matching output is fine in both contexts.
+        for ctx in [ExecCtx::Legacy, ExecCtx::SegwitV0] {
+            let res = run(ctx, TxTemplate::dummy(), EcdsaSighashType::Single);
+            assert_eq!(res.error, None, "{:?}", ctx);
+            assert!(!res.success);
+        }
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn taproot_commitments() {
+        use bitcoin::hex::FromHex;
+        use bitcoin::taproot::{LeafVersion, TaprootBuilder};
+
+        // The first script path spend of the BIP 341 wallet test vectors.
+        let script = ScriptBuf::from_hex(
+            "20d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8ac",
+        )
+        .unwrap();
+        let output_key =
+            Vec::from_hex("147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3")
+                .unwrap();
+        let control_block =
+            Vec::from_hex("c1187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27")
+                .unwrap();
+        assert!(verify_taproot_commitment(
+            &output_key,
+            &control_block,
+            &script
+        ));
+        // The parity of the output key is committed to.
+        let mut wrong_parity = control_block.clone();
+        wrong_parity[0] = 0xc0;
+        assert!(!verify_taproot_commitment(
+            &output_key,
+            &wrong_parity,
+            &script
+        ));
+
+        // A two-leaf tree, where each leaf has the other one as its path.
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let (internal_key, _) = sk.x_only_public_key(&SECP);
+        let leaves = [
+            bitcoin::script::Builder::new()
+                .push_opcode(OP_PUSHNUM_1)
+                .into_script(),
+            bitcoin::script::Builder::new()
+                .push_opcode(OP_PUSHNUM_2)
+                .into_script(),
+        ];
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(1, leaves[0].clone())
+            .unwrap()
+            .add_leaf(1, leaves[1].clone())
+            .unwrap()
+            .finalize(&SECP, internal_key)
+            .unwrap();
+        let output_key = spend_info.output_key().to_x_only_public_key().serialize();
+        for (i, leaf) in leaves.iter().enumerate() {
+            let control_block = spend_info
+                .control_block(&(leaf.clone(), LeafVersion::TapScript))
+                .unwrap()
+                .serialize();
+            assert_eq!(control_block.len(), 33 + 32);
+            assert!(verify_taproot_commitment(&output_key, &control_block, leaf));
+            // The other leaf isn't proven by this control block.
+            assert!(!verify_taproot_commitment(
+                &output_key,
+                &control_block,
+                &leaves[1 - i]
+            ));
+            // Control blocks must be 33 + 32k bytes long.
+            let mut truncated = control_block.clone();
+            truncated.pop();
+            assert!(!verify_taproot_commitment(&output_key, &truncated, leaf));
+        }
+    }
 }
@@ -0,0 +1,230 @@
+use std::collections::BTreeSet;
+
+use bitcoin::opcodes::{all::*, Opcode};
+
+use crate::analysis::stack_effect;
+
+/// The indices of the witness elements a value depends on.
+pub type Taint = BTreeSet<usize>;
+
+/// The taint of every value on the stacks, see [crate::Options::track_taint].
+///
+/// Values depend on the operands of the opcodes producing them, and on the
+/// conditions of the branches they are produced in.
+#[derive(Debug, Clone)]
+pub(crate) struct TaintState {
+    pub(crate) stack: Vec<Taint>,
+    altstack: Vec<Taint>,
+    /// The taint of the condition of every nested conditional, each including
+    /// the taint of the ones enclosing it.
+    control: Vec<Taint>,
+    /// The taint of all values consumed by the VERIFY opcodes, which the success
+    /// of the script depends on.
+    pub(crate) verified: Taint,
+}
+
+/// What needs to be known about the stacks before an opcode to follow its taint.
+pub(crate) struct TaintPre {
+    pub(crate) depth: usize,
+    /// The operand of OP_PICK and OP_ROLL.
+    pub(crate) n: Option<usize>,
+}
+
+impl TaintState {
+    /// Every witness element only depends on itself.
+    pub(crate) fn new(witness_len: usize) -> Self {
+        Self {
+            stack: (0..witness_len).map(|i| Taint::from([i])).collect(),
+            altstack: Vec::new(),
+            control: Vec::new(),
+            verified: Taint::new(),
+        }
+    }
+
+    fn control(&self) -> Taint {
+        self.control.last().cloned().unwrap_or_default()
+    }
+
+    /// Follows a data push.
+    pub(crate) fn push(&mut self) {
+        let t = self.control();
+        self.stack.push(t);
+    }
+
+    /// Follows [op], given the stack depths after it was executed.
+    ///
+    /// Like [crate::Exec::exec_opcode], this has to be called for
+    /// conditionals in unexecuted branches as well.
+    pub(crate) fn apply(
+        &mut self,
+        op: Opcode,
+        exec: bool,
+        custom: bool,
+        pre: TaintPre,
+        depth: usize,
+        altdepth: usize,
+    ) {
+        let d = pre.depth;
+        if !custom {
+            match op {
+                OP_IF | OP_NOTIF => {
+                    let mut t = self.control();
+                    if exec {
+                        t.extend(self.stack.pop().unwrap_or_default());
+                    }
+                    self.control.push(t);
+                    return;
+                }
+                OP_ELSE => return,
+                OP_ENDIF => {
+                    self.control.pop();
+                    return;
+                }
+                _ => {}
+            }
+        }
+
+        // The lowest stack position changed by the opcode.
+        let mut low = d.min(depth);
+        match op {
+            _ if custom => {
+                self.taint_all(depth, altdepth);
+                return;
+            }
+            OP_PUSHNUM_NEG1 | OP_PUSHNUM_1 | OP_PUSHNUM_2 | OP_PUSHNUM_3 | OP_PUSHNUM_4
+            | OP_PUSHNUM_5 | OP_PUSHNUM_6 | OP_PUSHNUM_7 | OP_PUSHNUM_8 | OP_PUSHNUM_9
+            | OP_PUSHNUM_10 | OP_PUSHNUM_11 | OP_PUSHNUM_12 | OP_PUSHNUM_13 | OP_PUSHNUM_14
+            | OP_PUSHNUM_15 | OP_PUSHNUM_16 | OP_DEPTH => self.stack.push(Taint::new()),
+            OP_TOALTSTACK => {
+                let t = self.stack.pop().unwrap_or_default();
+                self.altstack.push(t);
+                // Moving a value in a branch makes the altstack depend on it.
+                let ctrl = self.control();
+                self.altstack.last_mut().unwrap().extend(ctrl);
+            }
+            OP_FROMALTSTACK => {
+                let t = self.altstack.pop().unwrap_or_default();
+                self.stack.push(t);
+            }
+            OP_DUP | OP_2DUP | OP_3DUP | OP_OVER | OP_2OVER => {
+                let (from, n) = match op {
+                    OP_DUP => (d - 1, 1),
+                    OP_2DUP => (d - 2, 2),
+                    OP_3DUP => (d - 3, 3),
+                    OP_OVER => (d - 2, 1),
+                    _ => (d - 4, 2),
+                };
+                self.stack.extend_from_within(from..from + n);
+            }
+            OP_IFDUP => {
+                if depth > d {
+                    self.stack.push(self.stack[d - 1].clone());
+                }
+            }
+            OP_NIP => {
+                self.stack.remove(d - 2);
+                low = d - 2;
+            }
+            OP_SWAP => {
+                self.stack.swap(d - 1, d - 2);
+                low = d - 2;
+            }
+            OP_ROT => {
+                self.stack[d - 3..].rotate_left(1);
+                low = d - 3;
+            }
+            OP_2SWAP => {
+                self.stack[d - 4..].rotate_left(2);
+                low = d - 4;
+            }
+            OP_2ROT => {
+                self.stack[d - 6..].rotate_left(2);
+                low = d - 6;
+            }
+            OP_TUCK => {
+                let t = self.stack[d - 1].clone();
+                self.stack.insert(d - 2, t);
+                low = d - 2;
+            }
+            // The value picked depends on n as well.
+            OP_PICK | OP_ROLL => {
+                let Some(n) = pre.n else {
+                    self.taint_all(depth, altdepth);
+                    return;
+                };
+                let nt = self.stack.pop().unwrap_or_default();
+                let idx = d - 2 - n;
+                let mut t = if op == OP_PICK {
+                    low = depth - 1;
+                    self.stack[idx].clone()
+                } else {
+                    low = idx;
+                    self.stack.remove(idx)
+                };
+                t.extend(nt);
+                self.stack.push(t);
+            }
+            OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                let consumed = d + usize::from(op == OP_CHECKMULTISIG) - depth;
+                let t = self.merge(consumed);
+                low = depth.saturating_sub(1);
+                if op == OP_CHECKMULTISIG {
+                    self.stack.push(t);
+                } else {
+                    self.verified.extend(t);
+                }
+            }
+            op => match stack_effect(op) {
+                Some((pops, pushes)) => {
+                    let t = self.merge(pops);
+                    low = d - pops;
+                    if is_verify(op) {
+                        self.verified.extend(t.iter().copied());
+                    }
+                    for _ in 0..pushes {
+                        self.stack.push(t.clone());
+                    }
+                }
+                None => {
+                    self.taint_all(depth, altdepth);
+                    return;
+                }
+            },
+        }
+
+        if is_verify(op) {
+            let ctrl = self.control();
+            self.verified.extend(ctrl);
+        }
+        let ctrl = self.control();
+        for t in self.stack.iter_mut().skip(low) {
+            t.extend(ctrl.iter().copied());
+        }
+
+        if self.stack.len() != depth || self.altstack.len() != altdepth {
+            // Shouldn't happen, but be conservative rather than misaligned.
+            self.taint_all(depth, altdepth);
+        }
+    }
+
+    /// Pops [n] values and returns the union of their taint.
+    fn merge(&mut self, n: usize) -> Taint {
+        let at = self.stack.len().saturating_sub(n);
+        self.stack.split_off(at).into_iter().flatten().collect()
+    }
+
+    /// Assumes every value depends on every value, for opcodes we can't follow.
+    fn taint_all(&mut self, depth: usize, altdepth: usize) {
+        let mut t = self.control();
+        t.extend(self.stack.iter().chain(&self.altstack).flatten().copied());
+        self.stack = vec![t.clone(); depth];
+        self.altstack = vec![t; altdepth];
+    }
+}
+
+fn is_verify(op: Opcode) -> bool {
+    matches!(
+        op,
+        OP_VERIFY | OP_EQUALVERIFY | OP_NUMEQUALVERIFY | OP_CHECKSIGVERIFY | OP_CHECKMULTISIGVERIFY
+    )
+}
@@ -1,5 +1,12 @@
 use crate::data_structures::ScriptIntError;
-use bitcoin::script::write_scriptint;
+use bitcoin::consensus::Encodable;
+use bitcoin::hashes::{Hash, HashEngine};
+use bitcoin::opcodes::{all::*, Opcode};
+use bitcoin::script::{Instruction, PushBytes, Script, ScriptBuf};
+use bitcoin::taproot::TapLeafHash;
+use core::cell::OnceCell;
+use core::ops::Range;
+use std::collections::HashMap;
 
 ///  A data type to abstract out the condition stack during script execution.
 ///
@@ -15,6 +22,7 @@ use bitcoin::script::write_scriptint;
 /// This uses an optimized implementation that does not materialize the
 /// actual stack. Instead, it just stores the size of the would-be stack,
 /// and the position of the first false value in it.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConditionStack {
     /// The size of the implied stack.
     size: usize,
@@ -27,13 +35,23 @@ impl ConditionStack {
     /// A constant for first_false_pos to indicate there are no falses.
     const NO_FALSE: usize = usize::MAX;
 
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
         Self {
             size: 0,
             first_false_pos: Self::NO_FALSE,
         }
     }
 
+    /// The number of nested conditionals.
+    pub const fn depth(&self) -> usize {
+        self.size
+    }
+
+    /// The nesting level of the outermost false value, or [None] if all are true.
+    pub fn first_false(&self) -> Option<usize> {
+        (!self.all_true()).then_some(self.first_false_pos)
+    }
+
     pub fn all_true(&self) -> bool {
         self.first_false_pos == Self::NO_FALSE
     }
@@ -80,27 +98,321 @@ impl ConditionStack {
             true
         }
     }
+
+    /// Returns whether the innermost branch is being executed, or [None] if
+    /// the stack is empty.
+    ///
+    /// This is the case when no value up to the top is false. The value of
+    /// the top itself can't be observed when a false value lies below it.
+    pub fn top_active(&self) -> Option<bool> {
+        if self.size == 0 {
+            None
+        } else {
+            Some(self.all_true())
+        }
+    }
+}
+
+impl Default for ConditionStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Checks whether [data] was pushed using the smallest possible push opcode.
+///
+/// Equivalent to Bitcoin Core's `CheckMinimalPush`.
+pub fn check_minimal_push(data: &[u8], opcode: Opcode) -> bool {
+    let op = opcode.to_u8();
+    if data.is_empty() {
+        // Should have used OP_0.
+        op == OP_PUSHBYTES_0.to_u8()
+    } else if data.len() == 1 && data[0] >= 1 && data[0] <= 16 {
+        // Should have used OP_1 .. OP_16.
+        false
+    } else if data.len() == 1 && data[0] == 0x81 {
+        // Should have used OP_1NEGATE.
+        false
+    } else if data.len() <= 75 {
+        // Must have used a direct push (opcode indicating number of bytes pushed + those bytes).
+        op as usize == data.len()
+    } else if data.len() <= 255 {
+        // Must have used OP_PUSHDATA.
+        op == OP_PUSHDATA1.to_u8()
+    } else if data.len() <= 65535 {
+        // Must have used OP_PUSHDATA2.
+        op == OP_PUSHDATA2.to_u8()
+    } else {
+        true
+    }
+}
+
+/// Checks whether [sig], including the trailing sighash type byte, is a
+/// strict DER encoded ECDSA signature.
+///
+/// Equivalent to Bitcoin Core's `IsValidSignatureEncoding`.
+pub fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    // Format: 0x30 [total-length] 0x02 [R-length] [R] 0x02 [S-length] [S] [sighash]
+
+    // Minimum and maximum size constraints.
+    if sig.len() < 9 || sig.len() > 73 {
+        return false;
+    }
+    // A signature is of type 0x30 (compound).
+    if sig[0] != 0x30 {
+        return false;
+    }
+    // Make sure the length covers the entire signature.
+    if sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    // Make sure the length of the S element is still inside the signature.
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    // Verify that the length of the signature matches the sum of the length
+    // of the elements.
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() {
+        return false;
+    }
+
+    // Check whether the R element is an integer.
+    if sig[2] != 0x02 {
+        return false;
+    }
+    // Zero-length integers are not allowed for R.
+    if len_r == 0 {
+        return false;
+    }
+    // Negative numbers are not allowed for R.
+    if sig[4] & 0x80 != 0 {
+        return false;
+    }
+    // Null bytes at the start of R are not allowed, unless R would
+    // otherwise be interpreted as a negative number.
+    if len_r > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 {
+        return false;
+    }
+
+    // Check whether the S element is an integer.
+    if sig[len_r + 4] != 0x02 {
+        return false;
+    }
+    // Zero-length integers are not allowed for S.
+    if len_s == 0 {
+        return false;
+    }
+    // Negative numbers are not allowed for S.
+    if sig[len_r + 6] & 0x80 != 0 {
+        return false;
+    }
+    // Null bytes at the start of S are not allowed, unless S would otherwise be
+    // interpreted as a negative number.
+    if len_s > 1 && sig[len_r + 6] == 0x00 && sig[len_r + 7] & 0x80 == 0 {
+        return false;
+    }
+
+    true
+}
+
+/// Checks whether [pk] has the format of a compressed or uncompressed public
+/// key, without checking that it is a valid point.
+///
+/// Equivalent to Bitcoin Core's `IsCompressedOrUncompressedPubKey`.
+pub fn is_compressed_or_uncompressed_pubkey(pk: &[u8]) -> bool {
+    match pk.first() {
+        Some(0x04) => pk.len() == 65,
+        Some(0x02) | Some(0x03) => pk.len() == 33,
+        _ => false,
+    }
+}
+
+/// Computes the BIP 341 leaf hash of [script], i.e. the "TapLeaf" tagged hash
+/// of the leaf version and the length-prefixed script.
+///
+/// The leaf version is hashed as is, Tapscript leaves use 0xc0.
+pub fn tapleaf_hash(leaf_version: u8, script: &Script) -> [u8; 32] {
+    let mut engine = TapLeafHash::engine();
+    engine.input(&[leaf_version]);
+    script
+        .consensus_encode(&mut engine)
+        .expect("engines don't error");
+    TapLeafHash::from_engine(engine).to_byte_array()
+}
+
+/// Returns a script pushing [data] with the smallest possible push opcode.
+///
+/// This is the encoding accepted by [check_minimal_push], i.e. single-byte
+/// values 1 to 16 and 0x81 use OP_1 .. OP_16 and OP_1NEGATE.
+pub fn minimal_push_opcode(data: &[u8]) -> ScriptBuf {
+    let mut script = ScriptBuf::new();
+    match data {
+        [n @ 1..=16] => script.push_opcode(Opcode::from(OP_PUSHNUM_1.to_u8() + n - 1)),
+        [0x81] => script.push_opcode(OP_PUSHNUM_NEG1),
+        _ => {
+            let data = <&PushBytes>::try_from(data).expect("pushes are smaller than 4GB");
+            script.push_slice(data);
+        }
+    }
+    script
+}
+
+/// Returns the instructions of [script] along with the byte range each of them
+/// occupies, including the opcode and length prefix of pushes.
+///
+/// Parsing stops at the first instruction that doesn't parse.
+pub fn instructions_with_spans(script: &Script) -> Vec<(Range<usize>, Instruction<'_>)> {
+    let mut ret = Vec::new();
+    for res in script.instruction_indices() {
+        let Ok((pos, ins)) = res else { break };
+        let len = match ins {
+            Instruction::Op(_) => 1,
+            Instruction::PushBytes(data) => {
+                let prefix_len = match Opcode::from(script.as_bytes()[pos]) {
+                    OP_PUSHDATA1 => 2,
+                    OP_PUSHDATA2 => 3,
+                    OP_PUSHDATA4 => 5,
+                    _ => 1,
+                };
+                prefix_len + data.len()
+            }
+        };
+        ret.push((pos..pos + len, ins));
+    }
+    ret
+}
+
+/// A script with lazily computed and cached static metrics.
+///
+/// Each metric is computed on first access only, which avoids re-parsing
+/// scripts that are analyzed repeatedly.
+pub struct ScriptInfo {
+    script: ScriptBuf,
+    push_only: OnceCell<bool>,
+    sigops: OnceCell<usize>,
+    histogram: OnceCell<HashMap<u8, usize>>,
+}
+
+impl ScriptInfo {
+    pub fn new(script: ScriptBuf) -> Self {
+        Self {
+            script,
+            push_only: OnceCell::new(),
+            sigops: OnceCell::new(),
+            histogram: OnceCell::new(),
+        }
+    }
+
+    pub fn script(&self) -> &Script {
+        &self.script
+    }
+
+    pub fn into_script(self) -> ScriptBuf {
+        self.script
+    }
+
+    pub fn is_push_only(&self) -> bool {
+        *self.push_only.get_or_init(|| self.script.is_push_only())
+    }
+
+    /// The number of signature operations, counted accurately like in P2SH
+    /// and witness scripts, i.e. using the key count of CHECKMULTISIG.
+    pub fn sigop_count(&self) -> usize {
+        *self.sigops.get_or_init(|| self.script.count_sigops())
+    }
+
+    /// The number of occurrences of each opcode byte, including push opcodes.
+    ///
+    /// Counting stops at the first instruction that doesn't parse.
+    pub fn opcode_histogram(&self) -> &HashMap<u8, usize> {
+        self.histogram.get_or_init(|| {
+            let mut histogram = HashMap::new();
+            for res in self.script.instruction_indices() {
+                let Ok((pos, _)) = res else { break };
+                *histogram.entry(self.script.as_bytes()[pos]).or_insert(0) += 1;
+            }
+            histogram
+        })
+    }
+}
+
+/// Returns minimally encoded scriptint in a fixed buffer, along with the
+/// number of bytes used.
+///
+/// This avoids the allocation of [scriptint_vec] when the encoding is only
+/// needed temporarily. Every [i64] fits: magnitudes of 2^63 and above take
+/// 8 bytes plus a sign byte, which only happens for [i64::MIN].
+pub fn scriptint_arr(n: i64) -> ([u8; 9], usize) {
+    let mut buf = [0u8; 9];
+    let mut len = 0;
+    let mut abs = n.unsigned_abs();
+    while abs > 0 {
+        buf[len] = abs as u8;
+        len += 1;
+        abs >>= 8;
+    }
+    if len > 0 {
+        // Add a byte for the sign bit if the most significant byte uses it.
+        if buf[len - 1] & 0x80 != 0 {
+            len += 1;
+        }
+        if n < 0 {
+            buf[len - 1] |= 0x80;
+        }
+    }
+    (buf, len)
 }
 
 /// Returns minimally encoded scriptint as a byte vector.
 pub fn scriptint_vec(n: i64) -> Vec<u8> {
-    let mut buf = [0u8; 8];
-    let len = write_scriptint(&mut buf, n);
+    let (buf, len) = scriptint_arr(n);
     buf[0..len].to_vec()
 }
 
+/// Returns the minimal encoding of the number encoded in [v].
+///
+/// This is what a push rejected with [ScriptIntError::NonMinimalPush] should
+/// have been, e.g. 0x0100 becomes 0x01 and the negative zero 0x80 becomes
+/// the empty vector. Unlike the decoding functions, [v] can be of any size.
+pub fn minimal_form(v: &[u8]) -> Vec<u8> {
+    let mut ret = v.to_vec();
+    let negative = match ret.last_mut() {
+        Some(last) => {
+            let negative = *last & 0x80 != 0;
+            *last &= 0x7f;
+            negative
+        }
+        None => return ret,
+    };
+    while ret.last() == Some(&0) {
+        ret.pop();
+    }
+    match ret.last_mut() {
+        // Zero has no sign.
+        None => {}
+        Some(last) if *last & 0x80 != 0 => ret.push(if negative { 0x80 } else { 0x00 }),
+        Some(last) if negative => *last |= 0x80,
+        Some(_) => {}
+    }
+    ret
+}
+
 /// Decodes an interger in script format with flexible size limit.
 ///
 /// Note that in the majority of cases, you will want to use either
 /// [read_scriptint] or [read_scriptint_non_minimal] instead.
 ///
-/// Panics if max_size exceeds 8.
+/// Returns [ScriptIntError::MaxSizeTooLarge] if max_size exceeds 8.
 pub fn read_scriptint_size(
     v: &[u8],
     max_size: usize,
     minimal: bool,
 ) -> Result<i64, ScriptIntError> {
-    assert!(max_size <= 8);
+    if max_size > 8 {
+        return Err(ScriptIntError::MaxSizeTooLarge);
+    }
 
     if v.len() > max_size {
         return Err(ScriptIntError::NumericOverflow);
@@ -141,8 +453,249 @@ fn scriptint_parse(v: &[u8]) -> i64 {
         .iter()
         .fold((0, 0), |(acc, sh), n| (acc + ((*n as i64) << sh), sh + 8));
     if v[v.len() - 1] & 0x80 != 0 {
-        ret &= (1 << (sh - 1)) - 1;
+        // Avoid overflowing on 8-byte numbers where the mask is i64::MAX.
+        ret &= (1i64 << (sh - 1)).wrapping_sub(1);
         ret = -ret;
     }
     ret
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scriptint_arr_matches_write_scriptint() {
+        let mut values = vec![0, 255, -255, i64::MAX, i64::MIN + 1];
+        values.extend((0..63).flat_map(|i| [1i64 << i, -(1i64 << i), (1i64 << i) - 1]));
+        for n in values {
+            let mut expected = [0u8; 8];
+            let len = bitcoin::script::write_scriptint(&mut expected, n);
+            let (buf, arr_len) = scriptint_arr(n);
+            assert_eq!(&buf[..arr_len], &expected[..len], "{}", n);
+        }
+    }
+
+    #[test]
+    fn scriptint_arr_encodes_i64_min() {
+        let (buf, len) = scriptint_arr(i64::MIN);
+        assert_eq!(&buf[..len], &[0, 0, 0, 0, 0, 0, 0, 0x80, 0x80]);
+        assert_eq!(scriptint_vec(i64::MIN).len(), 9);
+    }
+
+    #[test]
+    fn read_scriptint_size_rejects_max_size_over_8() {
+        let res = read_scriptint_size(&[1; 9], 9, true);
+        assert_eq!(res, Err(ScriptIntError::MaxSizeTooLarge));
+        assert_eq!(
+            read_scriptint_size(&[], 9, false),
+            Err(ScriptIntError::MaxSizeTooLarge)
+        );
+        assert_eq!(
+            read_scriptint_size(&[1; 9], 8, true),
+            Err(ScriptIntError::NumericOverflow)
+        );
+    }
+
+    #[test]
+    fn signature_encoding() {
+        // r = 1, s = 1, SIGHASH_ALL
+        let valid = [0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01];
+        assert!(is_valid_signature_encoding(&valid));
+
+        let malformed: [&[u8]; 6] = [
+            // wrong total length
+            &[0x30, 0x07, 0x02, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01],
+            // wrong R length
+            &[0x30, 0x06, 0x02, 0x02, 0x01, 0x02, 0x01, 0x01, 0x01],
+            // negative R
+            &[0x30, 0x06, 0x02, 0x01, 0x81, 0x02, 0x01, 0x01, 0x01],
+            // negative S
+            &[0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x81, 0x01],
+            // excess padding of R
+            &[0x30, 0x07, 0x02, 0x02, 0x00, 0x01, 0x02, 0x01, 0x01, 0x01],
+            // not a compound structure
+            &[0x31, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01],
+        ];
+        for sig in malformed {
+            assert!(!is_valid_signature_encoding(sig), "{:?}", sig);
+        }
+    }
+
+    #[test]
+    fn minimal_push_thresholds() {
+        let direct = |len: usize| Opcode::from(len as u8);
+        let cases: [(usize, Opcode, Opcode); 6] = [
+            (0, OP_PUSHBYTES_0, OP_PUSHDATA1),
+            (1, direct(1), OP_PUSHDATA1),
+            (75, direct(75), OP_PUSHDATA1),
+            (76, OP_PUSHDATA1, OP_PUSHDATA2),
+            (255, OP_PUSHDATA1, OP_PUSHDATA2),
+            (256, OP_PUSHDATA2, OP_PUSHDATA4),
+        ];
+        for (len, canonical, non_canonical) in cases {
+            // 0x17 doesn't have a dedicated opcode.
+            let data = vec![0x17; len];
+            assert!(check_minimal_push(&data, canonical), "{}", len);
+            assert!(!check_minimal_push(&data, non_canonical), "{}", len);
+        }
+        assert!(check_minimal_push(&[0; 65535], OP_PUSHDATA2));
+        assert!(!check_minimal_push(&[0; 65535], OP_PUSHDATA4));
+
+        // Single bytes with a dedicated opcode can't be pushed as data.
+        for byte in [1, 16, 0x81] {
+            assert!(!check_minimal_push(&[byte], OP_PUSHBYTES_1), "{}", byte);
+        }
+        assert!(check_minimal_push(&[0], OP_PUSHBYTES_1));
+        assert!(check_minimal_push(&[17], OP_PUSHBYTES_1));
+    }
+
+    #[test]
+    fn minimal_push_opcode_matches_core() {
+        // The expected encodings are those of Core's `CScript() << CScriptNum(n)`
+        // for numbers and `CScript() << data` for the blob.
+        let blob = [0xab; 100];
+        let mut blob_push = vec![OP_PUSHDATA1.to_u8(), 100];
+        blob_push.extend(blob);
+        let cases: [(&[u8], Vec<u8>); 6] = [
+            (&[], vec![0x00]),
+            (&[1], vec![0x51]),
+            (&[16], vec![0x60]),
+            (&[17], vec![0x01, 0x11]),
+            (&[0x81], vec![0x4f]),
+            (&blob, blob_push),
+        ];
+        for (data, expected) in cases {
+            assert_eq!(minimal_push_opcode(data).to_bytes(), expected, "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn condition_stack_default() {
+        const NEW: ConditionStack = ConditionStack::new();
+        let stack = ConditionStack::default();
+        assert!(stack.all_true());
+        assert_eq!(stack.depth(), 0);
+        assert_eq!(stack, NEW);
+    }
+
+    #[test]
+    fn script_info_caches_each_metric() {
+        let script = bitcoin::script::Builder::new()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_PUSHNUM_1)
+            .into_script();
+        let info = ScriptInfo::new(script);
+        assert!(info.push_only.get().is_none());
+        assert!(info.sigops.get().is_none());
+        assert!(info.histogram.get().is_none());
+
+        // Each metric is cached on first access and returned as is after.
+        assert!(!info.is_push_only());
+        assert_eq!(info.sigop_count(), 1);
+        let histogram = info.opcode_histogram();
+        assert_eq!(histogram[&OP_PUSHNUM_1.to_u8()], 2);
+        assert_eq!(histogram.len(), 3);
+        assert_eq!(info.push_only.get(), Some(&false));
+        assert_eq!(info.sigops.get(), Some(&1));
+        for _ in 0..3 {
+            assert!(!info.is_push_only());
+            assert_eq!(info.sigop_count(), 1);
+            assert!(core::ptr::eq(info.opcode_histogram(), histogram));
+        }
+    }
+
+    #[test]
+    fn instruction_spans() {
+        // OP_1, a 3-byte push, a 76-byte PUSHDATA1 push and OP_ADD.
+        let mut bytes = vec![0x51, 0x03, 1, 2, 3, 0x4c, 76];
+        bytes.extend([7; 76]);
+        bytes.push(0x93);
+        let script = ScriptBuf::from(bytes);
+
+        let spans = instructions_with_spans(&script);
+        let ranges: Vec<_> = spans.iter().map(|(span, _)| span.clone()).collect();
+        assert_eq!(ranges, [0..1, 1..5, 5..83, 83..84]);
+        assert_eq!(spans[0].1, Instruction::Op(OP_PUSHNUM_1));
+        assert_eq!(spans[3].1, Instruction::Op(OP_ADD));
+        let Instruction::PushBytes(data) = spans[2].1 else {
+            panic!("not a push: {:?}", spans[2].1);
+        };
+        assert_eq!(data.as_bytes(), [7; 76]);
+
+        // A truncated push ends the instructions.
+        let script = ScriptBuf::from(vec![0x51, 0x4c, 5, 1]);
+        assert_eq!(instructions_with_spans(&script).len(), 1);
+    }
+
+    #[test]
+    fn minimal_forms() {
+        let cases: [(&[u8], &[u8]); 9] = [
+            (&[0x01, 0x00], &[0x01]),
+            (&[0x01, 0x00, 0x00, 0x00], &[0x01]),
+            (&[0x01, 0x80], &[0x81]),
+            (&[0x00, 0x01], &[0x00, 0x01]),
+            (&[0xff, 0x00], &[0xff, 0x00]),
+            (&[0xff, 0x00, 0x80], &[0xff, 0x80]),
+            (&[0x80], &[]),
+            (&[0x00, 0x00], &[]),
+            (&[], &[]),
+        ];
+        for (v, minimal) in cases {
+            assert_eq!(minimal_form(v), minimal, "{:x?}", v);
+            assert_eq!(
+                crate::read_scriptint(minimal, 4, true),
+                crate::read_scriptint(v, 4, false),
+                "{:x?}",
+                v
+            );
+        }
+    }
+
+    #[test]
+    fn condition_stack_top_active() {
+        let mut stack = ConditionStack::new();
+        assert_eq!(stack.top_active(), None);
+        stack.push(true);
+        assert_eq!(stack.top_active(), Some(true));
+        stack.push(false);
+        assert_eq!(stack.top_active(), Some(false));
+        assert_eq!(stack.first_false(), Some(1));
+        // A true branch nested in a false one is not executed.
+        stack.push(true);
+        assert_eq!(stack.top_active(), Some(false));
+        assert!(stack.toggle_top());
+        assert_eq!(stack.top_active(), Some(false));
+        assert!(stack.pop());
+        assert!(stack.toggle_top());
+        assert_eq!(stack.top_active(), Some(true));
+        assert!(stack.toggle_top());
+        assert_eq!(stack.top_active(), Some(false));
+        assert!(stack.pop());
+        assert_eq!(stack.top_active(), Some(true));
+        assert!(stack.pop());
+        assert_eq!(stack.top_active(), None);
+        assert!(!stack.pop());
+        assert!(!stack.toggle_top());
+    }
+
+    #[test]
+    fn tapleaf_hash_matches_bip341() {
+        use bitcoin::hex::FromHex;
+
+        // The first script path spend of the BIP 341 wallet test vectors.
+        let script = ScriptBuf::from_hex(
+            "20d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8ac",
+        )
+        .unwrap();
+        let expected = <[u8; 32]>::from_hex(
+            "5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21",
+        )
+        .unwrap();
+        assert_eq!(tapleaf_hash(0xc0, &script), expected);
+        // The hash commits to the leaf version.
+        assert_ne!(tapleaf_hash(0xc2, &script), expected);
+    }
+}
@@ -0,0 +1,48 @@
+//! Checks that the hash opcodes hash stack elements in place.
+//!
+//! This is its own test binary, as it replaces the global allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use bitcoin::opcodes::all::*;
+use bitcoin::script::Builder;
+use bitcoin_scriptexec::{Exec, ExecCtx, Options, TxTemplate};
+
+/// Counts the bytes allocated on each thread.
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATED.try_with(|n| n.set(n.get() + layout.size()));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+#[test]
+fn hashing_does_not_copy_the_element() {
+    let size = 100_000;
+    for op in [OP_RIPEMD160, OP_SHA1, OP_SHA256, OP_HASH160, OP_HASH256] {
+        let script = Builder::new().push_opcode(op).into_script();
+        let tx = TxTemplate::dummy();
+        let witness = vec![vec![1; size]];
+        let mut exec = Exec::new(ExecCtx::Legacy, Options::default(), tx, script, witness).unwrap();
+
+        let before = ALLOCATED.with(|n| n.get());
+        exec.exec_next().unwrap();
+        let allocated = ALLOCATED.with(|n| n.get()) - before;
+        assert!(allocated < size / 10, "{}: {} bytes", op, allocated);
+        assert_eq!(exec.stats().hashed_bytes, size);
+    }
+}